@@ -0,0 +1,187 @@
+// Assembles the mnemonics printed by `disassembler::mnemonic` back into ROM bytes, one
+// instruction per line with `;` starting a comment. Numbers are decimal, or hexadecimal and binary
+// with a 0x or 0b prefix. There are no labels, jumps and calls take plain addresses.
+//
+//   LD V0, 0x0A   ; V0 = 10
+//   DRW V0, V1, 5
+//   DB 0xF0, 0x90 ; raw sprite bytes
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut rom = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        assemble_line(line, &mut rom).map_err(|e| format!("line {}: {}", index + 1, e))?;
+    }
+    Ok(rom)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand {
+    V(u16),
+    VRange(u16, u16),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(u16),
+    Number(u16),
+}
+
+fn assemble_line(line: &str, rom: &mut Vec<u8>) -> Result<(), String> {
+    use Operand::*;
+
+    let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let operands = if rest.trim().is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',')
+            .map(parse_operand)
+            .collect::<Result<Vec<Operand>, String>>()?
+    };
+
+    let opcode = match (mnemonic.to_ascii_uppercase().as_str(), operands.as_slice()) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCD", [Number(n)]) => 0x00C0 | nibble(*n)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("SYS", [Number(nnn)]) => address(*nnn)?,
+        ("JP", [Number(nnn)]) => 0x1000 | address(*nnn)?,
+        ("JP", [V(0), Number(nnn)]) => 0xB000 | address(*nnn)?,
+        ("CALL", [Number(nnn)]) => 0x2000 | address(*nnn)?,
+        ("SE", [V(x), Number(nn)]) => 0x3000 | x << 8 | byte(*nn)?,
+        ("SNE", [V(x), Number(nn)]) => 0x4000 | x << 8 | byte(*nn)?,
+        ("SE", [V(x), V(y)]) => 0x5000 | x << 8 | y << 4,
+        ("LD", [IndirectI, VRange(x, y)]) => 0x5002 | x << 8 | y << 4,
+        ("LD", [VRange(x, y), IndirectI]) => 0x5003 | x << 8 | y << 4,
+        ("LD", [V(x), Number(nn)]) => 0x6000 | x << 8 | byte(*nn)?,
+        ("ADD", [V(x), Number(nn)]) => 0x7000 | x << 8 | byte(*nn)?,
+        ("LD", [V(x), V(y)]) => 0x8000 | x << 8 | y << 4,
+        ("OR", [V(x), V(y)]) => 0x8001 | x << 8 | y << 4,
+        ("AND", [V(x), V(y)]) => 0x8002 | x << 8 | y << 4,
+        ("XOR", [V(x), V(y)]) => 0x8003 | x << 8 | y << 4,
+        ("ADD", [V(x), V(y)]) => 0x8004 | x << 8 | y << 4,
+        ("SUB", [V(x), V(y)]) => 0x8005 | x << 8 | y << 4,
+        ("SUBN", [V(x), V(y)]) => 0x8007 | x << 8 | y << 4,
+        // The shifts are usually written with just Vx, which also does the right thing with the
+        // shift_vy_into_vx quirk
+        ("SHR", [V(x)]) => 0x8006 | x << 8 | x << 4,
+        ("SHR", [V(x), V(y)]) => 0x8006 | x << 8 | y << 4,
+        ("SHL", [V(x)]) => 0x800E | x << 8 | x << 4,
+        ("SHL", [V(x), V(y)]) => 0x800E | x << 8 | y << 4,
+        ("SNE", [V(x), V(y)]) => 0x9000 | x << 8 | y << 4,
+        ("LD", [I, Number(nnn)]) => 0xA000 | address(*nnn)?,
+        ("RND", [V(x), Number(nn)]) => 0xC000 | x << 8 | byte(*nn)?,
+        ("DRW", [V(x), V(y), Number(n)]) => 0xD000 | x << 8 | y << 4 | nibble(*n)?,
+        ("SKP", [V(x)]) => 0xE09E | x << 8,
+        ("SKNP", [V(x)]) => 0xE0A1 | x << 8,
+        ("LD", [I, Long(nnnn)]) => {
+            rom.extend_from_slice(&0xF000u16.to_be_bytes());
+            *nnnn
+        }
+        ("PLANE", [Number(n)]) => 0xF001 | nibble(*n)? << 8,
+        ("AUDIO", []) => 0xF002,
+        ("LD", [V(x), Dt]) => 0xF007 | x << 8,
+        ("LD", [V(x), K]) => 0xF00A | x << 8,
+        ("LD", [Dt, V(x)]) => 0xF015 | x << 8,
+        ("LD", [St, V(x)]) => 0xF018 | x << 8,
+        ("ADD", [I, V(x)]) => 0xF01E | x << 8,
+        ("LD", [F, V(x)]) => 0xF029 | x << 8,
+        ("LD", [Hf, V(x)]) => 0xF030 | x << 8,
+        ("LD", [B, V(x)]) => 0xF033 | x << 8,
+        ("PITCH", [V(x)]) => 0xF03A | x << 8,
+        ("LD", [IndirectI, V(x)]) => 0xF055 | x << 8,
+        ("LD", [V(x), IndirectI]) => 0xF065 | x << 8,
+        ("LD", [R, V(x)]) => 0xF075 | x << 8,
+        ("LD", [V(x), R]) => 0xF085 | x << 8,
+        ("DW", [Number(nnnn)]) => *nnnn,
+        // Raw bytes, e.g. sprite data, which don't have to fill a whole word
+        ("DB", bytes) if !bytes.is_empty() => {
+            for operand in bytes {
+                let Number(value) = operand else {
+                    return Err(format!("DB expects numbers, got {:?}", operand));
+                };
+                rom.push(byte(*value)? as u8);
+            }
+            return Ok(());
+        }
+        _ => return Err(format!("unknown instruction {}", line)),
+    };
+    rom.extend_from_slice(&opcode.to_be_bytes());
+    Ok(())
+}
+
+fn parse_operand(operand: &str) -> Result<Operand, String> {
+    let operand = operand.trim().to_ascii_uppercase();
+    let register = |name: &str| {
+        name.strip_prefix('V')
+            .filter(|digit| digit.len() == 1)
+            .and_then(|digit| u16::from_str_radix(digit, 16).ok())
+    };
+
+    Ok(match operand.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::Hf,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        _ => {
+            if let Some(x) = register(&operand) {
+                Operand::V(x)
+            } else if let Some((x, y)) = operand.split_once('-') {
+                match (register(x.trim()), register(y.trim())) {
+                    (Some(x), Some(y)) => Operand::VRange(x, y),
+                    _ => return Err(format!("invalid register range {}", operand)),
+                }
+            } else if let Some(value) = operand.strip_prefix("LONG") {
+                Operand::Long(parse_number(value.trim())?)
+            } else {
+                Operand::Number(parse_number(&operand)?)
+            }
+        }
+    })
+}
+
+fn parse_number(number: &str) -> Result<u16, String> {
+    let parsed = if let Some(hex) = number.strip_prefix("0X") {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(binary) = number.strip_prefix("0B") {
+        u16::from_str_radix(binary, 2)
+    } else {
+        number.parse()
+    };
+    parsed.map_err(|e| format!("invalid number {}: {}", number, e))
+}
+
+fn limit(value: u16, max: u16, what: &str) -> Result<u16, String> {
+    if value > max {
+        return Err(format!("{} {:#X} is larger than {:#X}", what, value, max));
+    }
+    Ok(value)
+}
+
+fn address(value: u16) -> Result<u16, String> {
+    limit(value, 0x0FFF, "address")
+}
+
+fn byte(value: u16) -> Result<u16, String> {
+    limit(value, 0x00FF, "byte")
+}
+
+fn nibble(value: u16) -> Result<u16, String> {
+    limit(value, 0x000F, "nibble")
+}
@@ -1,16 +1,179 @@
-use sdl2::audio::AudioCallback;
+use sdl2::audio::{AudioCallback, AudioDevice};
+use std::sync::{
+    atomic::{AtomicU32, AtomicUsize, Ordering},
+    Arc,
+};
+
+use super::buzzer::AudioBackend;
+
+// An f32 shared with the audio thread without locking, stored as its bit pattern
+#[derive(Clone, Debug)]
+pub struct SharedF32(Arc<AtomicU32>);
+
+impl SharedF32 {
+    pub fn new(value: f32) -> SharedF32 {
+        SharedF32(Arc::new(AtomicU32::new(value.to_bits())))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+// Queue of samples from the audio thread to the main thread. There is exactly one producer and
+// one consumer, so it works with atomics alone and `push` never locks or allocates. Samples are
+// dropped when the queue is full.
+#[derive(Clone)]
+pub struct SampleRing {
+    samples: Arc<[AtomicU32]>,
+    read: Arc<AtomicUsize>,
+    write: Arc<AtomicUsize>,
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> SampleRing {
+        SampleRing {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            read: Arc::new(AtomicUsize::new(0)),
+            write: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Called from the audio thread, returns false when the sample was dropped
+    pub fn push(&self, sample: f32) -> bool {
+        let write = self.write.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Acquire);
+        if write.wrapping_sub(read) == self.samples.len() {
+            return false;
+        }
+        self.samples[write % self.samples.len()].store(sample.to_bits(), Ordering::Relaxed);
+        self.write.store(write.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    // Called from the main thread, moves everything queued so far into `out`
+    pub fn drain(&self, out: &mut Vec<f32>) {
+        let read = self.read.load(Ordering::Relaxed);
+        let write = self.write.load(Ordering::Acquire);
+        let mut index = read;
+        while index != write {
+            let bits = self.samples[index % self.samples.len()].load(Ordering::Relaxed);
+            out.push(f32::from_bits(bits));
+            index = index.wrapping_add(1);
+        }
+        self.read.store(write, Ordering::Release);
+    }
+}
+
+// XO-CHIP plays a 128-bit pattern (16 bytes), one bit per sample, most significant bit first
+pub fn pattern_sample(pattern: &[u8; 16], position: usize) -> f32 {
+    let bit = position % 128;
+    if pattern[bit / 8] >> (7 - bit % 8) & 1 == 1 {
+        0.5
+    } else {
+        -0.5
+    }
+}
+
+// PolyBLEP correction for a step at phase 0, smoothing the samples within one `phase_inc` of it
+// instead of jumping between them. See
+// https://www.martin-finke.de/articles/audio-plugins-018-polyblep-oscillator/
+fn poly_blep(phase: f32, phase_inc: f32) -> f32 {
+    if phase < phase_inc {
+        let t = phase / phase_inc;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - phase_inc {
+        let t = (phase - 1.0) / phase_inc;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+// Band-limited square wave at `phase` (0.0 to 1.0), high for the first half of the period.
+// The rising edge at 0.0 and the falling edge at 0.5 are both corrected.
+pub fn square_sample(phase: f32, phase_inc: f32) -> f32 {
+    let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+    let smoothed = naive + poly_blep(phase, phase_inc) - poly_blep((phase + 0.5) % 1.0, phase_inc);
+    smoothed * 0.5
+}
+
 pub struct Square {
     pub phase_inc: f32,
     pub phase: f32,
+
+    // Tone frequency in Hz, can be changed at runtime and is picked up on the next block
+    pub tone: SharedF32,
+    pub sample_rate: f32,
+
+    // Output gain from 0.0 to 1.0, shared with the frontend like `tone`
+    pub volume: SharedF32,
+
+    // XO-CHIP audio pattern, played at `pattern_rate` bits per second instead of the square wave
+    pub pattern: Option<[u8; 16]>,
+    pub pattern_rate: f32,
+    pub pattern_position: f32,
+
+    // Everything played is also queued here while a recording is running
+    pub recording: Option<SampleRing>,
 }
 
 impl AudioCallback for Square {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        for x in out.iter_mut() {
-            *x = if self.phase < 0.5 { 0.5 } else { -0.5 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+        // Read once per block so a change never lands halfway through it
+        let volume = self.volume.get().clamp(0.0, 1.0);
+
+        if let Some(pattern) = &self.pattern {
+            let step = self.pattern_rate / self.sample_rate;
+            for x in out.iter_mut() {
+                *x = pattern_sample(pattern, self.pattern_position as usize) * volume;
+                self.pattern_position = (self.pattern_position + step) % 128.0;
+            }
+        } else {
+            self.phase_inc = self.tone.get() / self.sample_rate;
+            for x in out.iter_mut() {
+                *x = square_sample(self.phase, self.phase_inc) * volume;
+                self.phase = (self.phase + self.phase_inc) % 1.0;
+            }
         }
+
+        if let Some(recording) = &self.recording {
+            for &sample in out.iter() {
+                recording.push(sample);
+            }
+        }
+    }
+}
+
+// The SDL device pauses the callback while the buzzer is off, the settings are handed to the
+// audio thread through the `Square` it owns
+impl AudioBackend for AudioDevice<Square> {
+    fn beep(&mut self, on: bool) {
+        if on {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    fn set_tone(&mut self, hz: f32) {
+        self.lock().tone.set(hz);
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.lock().volume.set(volume);
+    }
+
+    // Without a pattern the callback falls back to the square wave
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, rate: f32) {
+        let mut square = self.lock();
+        square.pattern = pattern;
+        square.pattern_rate = rate;
     }
 }
@@ -0,0 +1,37 @@
+use super::chippy::Chippy;
+
+// An audio output for the buzzer. The SDL frontend implements it for its audio device, other
+// hosts can plug in e.g. Web Audio, or `NullAudio` to run silently.
+pub trait AudioBackend {
+    // Start or stop the buzzer
+    fn beep(&mut self, on: bool);
+
+    // Pitch of the square wave in Hz
+    fn set_tone(&mut self, hz: f32);
+
+    // From 0.0 (silent) to 1.0 (full scale)
+    fn set_volume(&mut self, volume: f32);
+
+    // XO-CHIP audio pattern played at `rate` bits per second instead of the square wave, backends
+    // that can't play patterns keep beeping the square wave
+    fn set_pattern(&mut self, _pattern: Option<[u8; 16]>, _rate: f32) {}
+}
+
+// Discards all sound, for headless runs and machines without an audio device
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn beep(&mut self, _on: bool) {}
+
+    fn set_tone(&mut self, _hz: f32) {}
+
+    fn set_volume(&mut self, _volume: f32) {}
+}
+
+// Bring the buzzer in line with the machine after a frame has run, it sounds while the sound
+// timer is nonzero, stretched and filtered by `min_beep_frames` and `beep_threshold`
+pub fn play_sound(backend: &mut dyn AudioBackend, chippy: &Chippy) {
+    backend.set_pattern(chippy.audio_pattern(), chippy.playback_rate());
+    backend.beep(chippy.beeping());
+}
@@ -10,137 +10,1013 @@
   https://github.com/mattmikolay/chip-8/
 */
 
-use sdl2::{audio::AudioSpecDesired, event::Event, keyboard::Keycode, pixels::Color};
-use std::{fs::File, io::Read};
+#[cfg(feature = "sdl")]
+use std::fs;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Read,
+    time::Duration,
+};
 
-use super::audio::Square;
+use super::disassembler;
+#[cfg(feature = "sdl")]
+use super::gzip;
+#[cfg(feature = "sdl")]
+use super::profile::Profile;
+use super::quirks::{Platform, Quirks};
+use super::random::Random;
+
+// XO-CHIP bitplanes, the first one is the regular CHIP-8 display
+const PLANE_1: u8 = 0b01;
+const PLANE_2: u8 = 0b10;
+
+// The built-in 4x5 hexadecimal font, stored in the interpreter area below 0x200. Each glyph is
+// five rows with the pixels in the high nibble.
+const FONT_ADDRESS: u16 = 0x050;
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// The SUPER-CHIP 8x10 font for the digits 0-9, stored right after the small one
+const BIG_FONT_ADDRESS: u16 = 0x0A0;
+const BIG_FONT: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+const DEFAULT_MEMORY_SIZE: usize = 4096;
+const DEFAULT_LOAD_ADDRESS: u16 = 0x200;
+// A beep of a frame or two can fall between audio callbacks and never be heard
+pub const DEFAULT_MIN_BEEP_FRAMES: u32 = 2;
+
+// Header of the blobs produced by `save_state`, bump the version whenever the layout changes
+
+const STATE_MAGIC: &[u8] = b"CHPY";
+const STATE_VERSION: u8 = 1;
+
+// Headers of files people commonly point the emulator at by mistake
+const FILE_SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\xFF\xD8\xFF", "a JPEG image"),
+    (b"\x89PNG", "a PNG image"),
+    (b"GIF8", "a GIF image"),
+    (b"PK\x03\x04", "a zip archive"),
+    (b"\x1F\x8B", "gzip compressed"),
+    (b"\x7FELF", "an executable"),
+];
+
+// What happened when the machine was stepped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepResult {
+    // The instruction executed normally
+    Continue,
+    // The program has stopped itself and won't make progress on its own
+    Halt,
+    // Fx0A is blocked until a key is pressed and released
+    WaitingForKey,
+    // Dxyn is stalled until the next frame by the display_wait quirk
+    WaitingForVblank,
+    // The opcode wasn't recognised and was skipped, only returned outside strict mode
+    UnknownOpcode(u16),
+    // PC reached a breakpoint, the instruction there has not been executed yet
+    Breakpoint(u16),
+}
+
+// Drawing counters accumulated since the machine was created or `reset_metrics` was called
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EmuMetrics {
+    // Dxyn instructions executed
+    pub draw_calls: u64,
+    // Draws that set VF because a pixel was turned off
+    pub collisions: u64,
+    // Pixels flipped by draws, counted once per plane
+    pub pixels_toggled: u64,
+}
 
 pub struct Chippy {
-    // 4K RAM in a CHIP-8 system
-    pub memory: [u8; 4096],
+    // 4K RAM in a CHIP-8 system, XO-CHIP has 64K. Always a power of two so addresses can be
+    // wrapped with a mask.
+    pub memory: Vec<u8>,
+
+    // Where ROMs are loaded and execution starts, 0x200 on almost every interpreter but e.g.
+    // 0x600 for ETI-660 programs. Takes effect on the next load or reset.
+    pub load_address: u16,
 
     // 16 general-purpose 8-bit registers, V0-VE, VF is a carry flag
     pub v: [u8; 16],
 
+    // SUPER-CHIP RPL user flags, saved and restored by Fx75/Fx85. These survive a reset.
+    pub rpl: [u8; 8],
+
     // Index Register
     pub i: u16,
 
     // Program Counter
     pub pc: u16,
 
-    // monochrome display of 64x32 pixels, which can be only on or off at one time.
-    pub display: [u8; 64 * 32],
+    // display of 64x32 pixels (128x64 in SUPER-CHIP high resolution mode). Every pixel holds one
+    // bit per XO-CHIP plane, so plain CHIP-8 programs only ever see 0 or 1.
+    pub display: Vec<u8>,
+    width: usize,
+    height: usize,
+
+    // Bitmask of the planes that drawing and clearing affect, selected by Fn01
+    planes: u8,
 
     // A stack to store return addresses
     pub stack: [u16; 16],
     pub sp: usize, // Stack pointer
 
-    // Buzzer will play when sound timer is 0, Both delay and sound timers count at 60Hz
+    // Set when a call overflowed or a return underflowed the stack and was skipped
+    stack_fault: bool,
+
+    // Buzzer will play when sound timer is 0, Both delay and sound timers count at 60Hz.
+    // They only count down in `tick_timers` between frames, never in the middle of one: every
+    // Fx07 in a frame reads the value the frame started with, unless an Fx15 in the same frame
+    // wrote a new one, which is then read back unchanged until the next tick.
     pub delay_timer: u8,
     pub sound_timer: u8,
 
+    // The buzzer sounds for at least `min_beep_frames` frames once the sound timer is set to
+    // `beep_threshold` or more, smaller values are silent. `beep_frames` latches how many frames
+    // it has left, see `beeping`.
+    pub min_beep_frames: u32,
+    pub beep_threshold: u8,
+    beep_frames: u32,
+
     // hexadecimal keypad, 0-9, A-F
     pub keypad: [bool; 16],
+    // Keypad as it was at the end of the previous frame, to tell new presses from held keys
+    previous_keypad: [bool; 16],
+
+    // Key latched by Fx0A, which waits until it is released before storing it
+    waiting_for_key: Option<usize>,
+
+    // Set by Dxyn and cleared by `tick_timers`, used by the display_wait quirk
+    drawn_this_frame: bool,
+
+    // Interpreter-specific behaviours, defaults to the classic CHIP-8 ones
+    pub quirks: Quirks,
+
+    // Report `StepResult::Halt` when a program jumps to itself, the usual way to signal it's done
+    pub halt_on_self_jump: bool,
 
-    // Audio handling through SDL
-    audio_subsystem: sdl2::AudioSubsystem,
-    audio_device: sdl2::audio::AudioDevice<Square>,
+    // Fail on unknown opcodes instead of skipping them and counting them in `unknown_opcode_count`
+    pub strict: bool,
+    unknown_opcode_count: u64,
+
+    // How many instructions are executed for every 60Hz frame
+    cycles_per_frame: u32,
+
+    // Alternatively a clock rate in instructions per second, with the fraction of an
+    // instruction left over from previous frames
+    clock_hz: Option<u32>,
+    pending_cycles: f64,
+
+    // XO-CHIP audio pattern loaded by F002 and the pitch register set by Fx3A
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+
+    // Called with the PC and opcode of every instruction before it executes
+    on_instruction: Option<Box<dyn FnMut(u16, u16)>>,
+
+    // Called with the address and value of every memory read and write made by an instruction
+    on_mem_read: Option<Box<dyn FnMut(u16, u8)>>,
+    on_mem_write: Option<Box<dyn FnMut(u16, u8)>>,
+
+    // Addresses to stop at before executing, `stopped_at` lets the next step resume past one
+    breakpoints: HashSet<u16>,
+    stopped_at: Option<u16>,
+
+    // Always-on pokes, address to value, written again after every frame so the program can't
+    // undo them
+    cheats: HashMap<u16, u8>,
+
+    // Generator for Cxnn, unpredictable unless a seed was set
+    rng: Random,
+
+    metrics: EmuMetrics,
+
+    // Count executed instructions by `disassembler::opcode_class` into `opcode_stats`, off by
+    // default so the map isn't touched on every cycle
+    pub profile: bool,
+    opcode_stats: HashMap<u16, u64>,
+
+    // Save states taken before each step, newest at the back, at most `rewind_capacity` of them
+    rewind: VecDeque<Vec<u8>>,
+    rewind_capacity: usize,
+}
+
+impl Default for Chippy {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chippy {
     pub fn new() -> Chippy {
-        let sdl_context = sdl2::init().unwrap();
-        let audio_subsystem = sdl_context.audio().unwrap();
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100), // Hz
-            channels: Some(1),
-            samples: None,
-        };
-        let audio_device = audio_subsystem
-            .open_playback(None, &desired_spec, |spec| {
-                // Initialize the square wave for audio
-                Square {
-                    phase_inc: 440.0 / spec.freq as f32,
-                    phase: 0.0,
-                }
-            })
-            .unwrap();
-        Chippy {
-            memory: [0; 4096],
+        let mut chippy = Chippy {
+            memory: vec![0; DEFAULT_MEMORY_SIZE],
+            load_address: DEFAULT_LOAD_ADDRESS,
             v: [0; 16],
+            rpl: [0; 8],
             i: 0,
-            pc: 0,
+            pc: DEFAULT_LOAD_ADDRESS,
             stack: [0; 16],
             sp: 0,
-            display: [0; 64 * 32],
+            stack_fault: false,
+            display: vec![0; 64 * 32],
+            width: 64,
+            height: 32,
+            planes: PLANE_1,
             keypad: [false; 16],
+            previous_keypad: [false; 16],
+            waiting_for_key: None,
+            drawn_this_frame: false,
             delay_timer: 0,
             sound_timer: 0,
-            audio_subsystem,
-            audio_device,
+            min_beep_frames: DEFAULT_MIN_BEEP_FRAMES,
+            beep_threshold: 1,
+            beep_frames: 0,
+            quirks: Quirks::default(),
+            strict: false,
+            halt_on_self_jump: false,
+            unknown_opcode_count: 0,
+            cycles_per_frame: 10,
+            clock_hz: None,
+            pending_cycles: 0.0,
+            audio_pattern: None,
+            pitch: 64,
+            on_instruction: None,
+            on_mem_read: None,
+            on_mem_write: None,
+            breakpoints: HashSet::new(),
+            stopped_at: None,
+            cheats: HashMap::new(),
+            rng: Random::new(),
+            metrics: EmuMetrics::default(),
+            profile: false,
+            opcode_stats: HashMap::new(),
+            rewind: VecDeque::new(),
+            rewind_capacity: 0,
+        };
+        chippy.init_font();
+        chippy
+    }
+
+    // Put the machine back into its power-on state, keeping the loaded memory
+    pub fn reset(&mut self) {
+        self.v = [0; 16];
+        self.i = 0;
+        self.pc = self.load_address;
+        self.stack = [0; 16];
+        self.sp = 0;
+        self.stack_fault = false;
+        self.set_resolution(64, 32);
+        self.planes = PLANE_1;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.beep_frames = 0;
+        self.waiting_for_key = None;
+        self.previous_keypad = [false; 16];
+        self.drawn_this_frame = false;
+        self.audio_pattern = None;
+        self.pitch = 64;
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Row-major `width() * height()` pixels, so the pixel at (x, y) is at `y * width() + x`
+    pub fn display_buffer(&self) -> &[u8] {
+        &self.display
+    }
+
+    // Whether the pixel is lit on any plane, coordinates outside the screen are never lit
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.display[y * self.width + x] != 0
+    }
+
+    // Switching between low and high resolution also clears the screen
+    fn set_resolution(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.display = vec![0; width * height];
+    }
+
+    pub fn audio_pattern(&self) -> Option<[u8; 16]> {
+        self.audio_pattern
+    }
+
+    // XO-CHIP pattern playback rate in bits per second, 4000Hz at the default pitch of 64
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // Replace all quirk flags with the preset for `platform`, see `Quirks::for_platform`
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.quirks = Quirks::for_platform(platform);
+    }
+
+    // Make Cxnn reproducible, the same seed always produces the same sequence of random bytes
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng.seed(seed);
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    // The timers always tick at 60Hz, once per frame, so the CPU clock is `cycles_per_frame * 60`.
+    // The default of 10 gives ~600 instructions per second, around what most ROMs expect.
+    pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u32) {
+        self.cycles_per_frame = cycles_per_frame;
+        self.clock_hz = None;
+    }
+
+    pub fn clock_hz(&self) -> Option<u32> {
+        self.clock_hz
+    }
+
+    // Run at a fixed number of instructions per second of real time instead of per frame, see
+    // `cycles_for_elapsed`. `set_cycles_per_frame` switches back.
+    pub fn set_clock_hz(&mut self, hz: u32) {
+        self.clock_hz = Some(hz);
+        self.pending_cycles = 0.0;
+    }
+
+    // How many instructions to run for a frame that took `elapsed`. With a clock rate the
+    // fractions are carried over, so e.g. 500Hz at 60fps alternates between 8 and 9
+    // instructions, and a dropped frame is made up for by the next one. Long stalls are capped at a
+    // quarter of a second so the emulator doesn't race to catch up.
+    pub fn cycles_for_elapsed(&mut self, elapsed: Duration) -> u32 {
+        let Some(hz) = self.clock_hz else {
+            return self.cycles_per_frame;
+        };
+        let seconds = elapsed.as_secs_f64().min(0.25);
+        self.pending_cycles += hz as f64 * seconds;
+        let cycles = self.pending_cycles.floor();
+        self.pending_cycles -= cycles;
+        cycles as u32
+    }
+
+    // We need to load the game from a file into memory, so we can execute its opcode.
+    // Settings from a `<rom>.cfg` profile next to it are applied too, see `Profile`. The profile
+    // is returned so the frontend can use its colours without reading the file again.
+    // A path of `-` reads the ROM from stdin instead, e.g. `cat game.ch8 | chippy -`.
+    // Embedders without a filesystem use `load_bytes` instead.
+    #[cfg(feature = "sdl")]
+    pub fn load_game(&mut self, game_path: &str) -> Result<Option<Profile>, String> {
+        let rom = read_game(game_path)?;
+        for warning in rom_warnings(&rom) {
+            eprintln!("Warning: {}: {}", game_path, warning);
+        }
+        self.load_bytes(&rom)
+            .map_err(|e| format!("{}: {}", game_path, e))?;
+        let profile = Profile::for_rom(game_path)?;
+        if let Some(profile) = &profile {
+            profile.apply(self);
+        }
+        Ok(profile)
+    }
+
+    // Resize memory, e.g. to 65536 bytes for XO-CHIP. Clears it apart from the fonts, so this
+    // has to happen before a ROM is loaded.
+    pub fn set_memory_size(&mut self, mem_size: usize) -> Result<(), String> {
+        if !mem_size.is_power_of_two() || !(DEFAULT_MEMORY_SIZE..=0x10000).contains(&mem_size) {
+            return Err(format!(
+                "Invalid memory size {}, must be a power of two from 4096 to 65536",
+                mem_size
+            ));
+        }
+        self.memory = vec![0; mem_size];
+        self.init_font();
+        Ok(())
+    }
+
+    pub fn mem_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    // Memory addresses wrap around at the end of memory
+    fn address_mask(&self) -> u16 {
+        (self.memory.len() - 1) as u16
+    }
+
+    // Load a ROM that is already in memory, e.g. fetched over the network or from `include_bytes!`
+    pub fn load_bytes(&mut self, rom: &[u8]) -> Result<(), String> {
+        // Programs start at the load address, anything running past the end of memory can't fit
+        let start = self.load_address as usize;
+        let max_size = self.memory.len().saturating_sub(start);
+        if rom.is_empty() {
+            return Err("ROM is empty".to_string());
+        }
+        if start >= self.memory.len() {
+            return Err(format!(
+                "Load address {:#05X} is outside of {} bytes of memory",
+                self.load_address,
+                self.memory.len()
+            ));
+        }
+        if rom.len() > max_size {
+            return Err(format!(
+                "ROM too large: {} bytes, max {} at {:#05X}",
+                rom.len(),
+                max_size,
+                self.load_address
+            ));
+        }
+
+        // Clear what's left of a previous ROM, e.g. when a longer version is reloaded in watch mode
+        self.memory[start..].fill(0);
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+
+        self.pc = self.load_address;
+        Ok(())
+    }
+
+    // Load a ROM from anything readable, like stdin or a decompressing reader
+    pub fn load_from_reader<R: Read>(&mut self, reader: R) -> Result<(), String> {
+        self.load_bytes(&read_rom(reader)?)
+    }
+
+    // Snapshot the machine state into a versioned binary blob, settings and audio are not included
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_MAGIC.len() + self.memory.len() + 128);
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+
+        state.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        state.extend_from_slice(&self.memory);
+        state.extend_from_slice(&self.v);
+        state.extend_from_slice(&self.i.to_le_bytes());
+        state.extend_from_slice(&self.pc.to_le_bytes());
+        for address in self.stack {
+            state.extend_from_slice(&address.to_le_bytes());
+        }
+        state.push(self.sp as u8);
+        state.push(self.delay_timer);
+        state.push(self.sound_timer);
+        state.extend(self.keypad.iter().map(|&pressed| pressed as u8));
+
+        state.extend_from_slice(&(self.width as u16).to_le_bytes());
+        state.extend_from_slice(&(self.height as u16).to_le_bytes());
+        state.push(self.planes);
+        state.extend_from_slice(&self.display);
+        state
+    }
+
+    // Restore a blob from `save_state`, the machine is left untouched if it can't be loaded
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader { data: state };
+        if reader.bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err("Not a Chippy save state".to_string());
+        }
+        let version = reader.u8()?;
+        if version != STATE_VERSION {
+            return Err(format!(
+                "Unsupported save state version {}, expected {}",
+                version, STATE_VERSION
+            ));
+        }
+
+        let memory_size = reader.u32()? as usize;
+        if memory_size != self.memory.len() {
+            return Err(format!(
+                "Save state has {} bytes of memory, expected {}",
+                memory_size,
+                self.memory.len()
+            ));
+        }
+        let memory = reader.bytes(memory_size)?;
+        let v = reader.bytes(16)?;
+        let i = reader.u16()?;
+        let pc = reader.u16()?;
+        let mut stack = [0; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let sp = reader.u8()? as usize;
+        if sp > stack.len() {
+            return Err(format!("Invalid stack pointer {} in save state", sp));
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let keypad = reader.bytes(16)?;
+
+        let width = reader.u16()? as usize;
+        let height = reader.u16()? as usize;
+        if !matches!((width, height), (64, 32) | (128, 64)) {
+            return Err(format!(
+                "Invalid resolution {}x{} in save state",
+                width, height
+            ));
+        }
+        let planes = reader.u8()?;
+        let display = reader.bytes(width * height)?;
+        if !reader.data.is_empty() {
+            return Err("Unexpected trailing data in save state".to_string());
+        }
+
+        self.memory.copy_from_slice(memory);
+        self.v.copy_from_slice(v);
+        self.i = i;
+        self.pc = pc;
+        self.stack = stack;
+        self.sp = sp;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.beep_frames = 0;
+        for (key, &pressed) in self.keypad.iter_mut().zip(keypad) {
+            *key = pressed != 0;
+        }
+        self.previous_keypad = self.keypad;
+        self.width = width;
+        self.height = height;
+        self.planes = planes & (PLANE_1 | PLANE_2);
+        self.display = display.to_vec();
+        self.waiting_for_key = None;
+        Ok(())
+    }
+
+    // Execute a single instruction without touching timers or rendering.
+    // Stops before executing an instruction at a breakpoint, stepping again resumes from there.
+    pub fn step(&mut self) -> Result<StepResult, String> {
+        if self.breakpoints.contains(&self.pc) && self.stopped_at != Some(self.pc) {
+            self.stopped_at = Some(self.pc);
+            return Ok(StepResult::Breakpoint(self.pc));
+        }
+        self.stopped_at = None;
+
+        if self.rewind_capacity > 0 {
+            if self.rewind.len() == self.rewind_capacity {
+                self.rewind.pop_front();
+            }
+            self.rewind.push_back(self.save_state());
+        }
+
+        self.emulate_cycle()
+    }
+
+    // Remember the state before each of the last `capacity` steps so `step_back` can undo them.
+    // Off (0) by default since it takes a save state every instruction.
+    pub fn set_rewind_capacity(&mut self, capacity: usize) {
+        self.rewind_capacity = capacity;
+        while self.rewind.len() > capacity {
+            self.rewind.pop_front();
+        }
+    }
+
+    // Undo the most recent step, returns false when there is nothing left to undo
+    pub fn step_back(&mut self) -> bool {
+        match self.rewind.pop_back() {
+            Some(state) => self.load_state(&state).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.stopped_at = None;
+    }
+
+    // Write a byte of memory from outside the program, e.g. from a debugger. Addresses past the
+    // end of memory are ignored.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        if let Some(byte) = self.memory.get_mut(addr as usize) {
+            *byte = value;
         }
     }
 
-    // We need to load the game from a file into memory, so we can execute its opcode
-    fn load_game(&mut self, game_path: &str) -> Result<(), String> {
-        let file = File::open(game_path).map_err(|e| e.to_string())?;
+    // Read a byte of memory without going through the read hook, 0 past the end of memory
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.memory.get(addr as usize).copied().unwrap_or(0)
+    }
 
-        // Programs start at 0x200, so we need to load the game into memory starting at that address
-        // `i` is a temporary pointer to the current address that we're loading the opcode into.
-        let mut i = 0x200;
-        for byte in file.bytes() {
-            self.memory[i] = byte.map_err(|e| e.to_string())?;
-            i += 1;
+    // Keep `value` at `addr`, e.g. to freeze a lives counter. Applied right away and again after
+    // every frame, see `tick_timers`.
+    pub fn add_cheat(&mut self, addr: u16, value: u8) -> Result<(), String> {
+        if addr as usize >= self.mem_size() {
+            return Err(format!(
+                "Cheat address {:#05X} is outside the {} bytes of memory",
+                addr,
+                self.mem_size()
+            ));
         }
+        self.cheats.insert(addr, value);
+        self.poke(addr, value);
+        Ok(())
+    }
+
+    pub fn remove_cheat(&mut self, addr: u16) {
+        self.cheats.remove(&addr);
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.cheats.clear();
+    }
+
+    fn apply_cheats(&mut self) {
+        for (&addr, &value) in &self.cheats {
+            if let Some(byte) = self.memory.get_mut(addr as usize) {
+                *byte = value;
+            }
+        }
+    }
+
+    // The registers and timers are public fields, these are for callers that only hold a
+    // shared reference, e.g. a debugger UI
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    // Return addresses currently on the stack, oldest first
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[..self.sp.min(self.stack.len())]
+    }
+
+    // The whole CPU state on a few lines, for logs and bug reports
+    pub fn dump(&self) -> String {
+        let mut dump = format!(
+            "PC: {:#05X}  I: {:#05X}  SP: {}  DT: {}  ST: {}\n",
+            self.pc, self.i, self.sp, self.delay_timer, self.sound_timer
+        );
+        for (offset, registers) in self.v.chunks(8).enumerate() {
+            let line: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(index, value)| format!("V{:X}: {:02X}", offset * 8 + index, value))
+                .collect();
+            dump.push_str(&line.join("  "));
+            dump.push('\n');
+        }
+        let stack: Vec<String> = self
+            .call_stack()
+            .iter()
+            .map(|address| format!("{:#05X}", address))
+            .collect();
+        dump.push_str(&format!("Stack: [{}]\n", stack.join(", ")));
+        dump
+    }
+
+    // `dump` followed by the whole of memory, for bug reports about self-modifying ROMs. The text
+    // header ends with a blank line, the raw memory image is the `mem_size()` bytes after it.
+    pub fn memory_dump(&self) -> Vec<u8> {
+        let header = format!("{}Memory: {} bytes\n\n", self.dump(), self.memory.len());
+        let mut dump = header.into_bytes();
+        dump.extend_from_slice(&self.memory);
+        dump
+    }
+
+    #[cfg(feature = "sdl")]
+    pub fn dump_memory(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.memory_dump()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn stack_fault(&self) -> bool {
+        self.stack_fault
+    }
+
+    pub fn unknown_opcode_count(&self) -> u64 {
+        self.unknown_opcode_count
+    }
+
+    pub fn metrics(&self) -> EmuMetrics {
+        self.metrics
+    }
+
+    pub fn reset_metrics(&mut self) {
+        self.metrics = EmuMetrics::default();
+    }
+
+    // How many times each instruction ran while `profile` was set, keyed like 0x8004 for 8xy4
+    pub fn opcode_stats(&self) -> &HashMap<u16, u64> {
+        &self.opcode_stats
+    }
 
-        self.pc = 0x200;
+    pub fn reset_opcode_stats(&mut self) {
+        self.opcode_stats.clear();
+    }
+
+    // Execute `cycles` instructions without a window, audio or event pump, ticking the timers
+    // once every `cycles_per_frame` instructions as if it was running at 60 frames per second.
+    // Returns early when a breakpoint is hit or the program halts.
+    pub fn run_headless(&mut self, cycles: u64) -> Result<(), String> {
+        let cycles_per_frame = self.cycles_per_frame.max(1) as u64;
+        for cycle in 1..=cycles {
+            match self.step()? {
+                StepResult::Breakpoint(_) | StepResult::Halt => break,
+                _ => {}
+            }
+            if cycle % cycles_per_frame == 0 {
+                self.tick_timers();
+            }
+        }
         Ok(())
     }
 
+    // Run headless until the program halts, by jumping to itself or with 00FD, and return how
+    // many instructions ran before the one that halted. Hitting `max_cycles` first is an error,
+    // the program is most likely stuck in a loop. Breakpoints are stepped over.
+    pub fn run_until_halt(&mut self, max_cycles: u64) -> Result<u64, String> {
+        let cycles_per_frame = self.cycles_per_frame.max(1) as u64;
+        let halt_on_self_jump = self.halt_on_self_jump;
+        self.halt_on_self_jump = true;
+        let mut cycles = 0;
+        let result = loop {
+            if cycles == max_cycles {
+                break Err(format!(
+                    "No halt after {} cycles, PC at {:#05X}",
+                    max_cycles, self.pc
+                ));
+            }
+            match self.step() {
+                Ok(StepResult::Halt) => break Ok(cycles),
+                Ok(StepResult::Breakpoint(_)) => continue,
+                Ok(_) => {}
+                Err(e) => break Err(e),
+            }
+            cycles += 1;
+            if cycles % cycles_per_frame == 0 {
+                self.tick_timers();
+            }
+        };
+        self.halt_on_self_jump = halt_on_self_jump;
+        result
+    }
+
+    // Run one 60Hz frame: up to `cycles_per_frame` instructions, then a timer tick. The frame
+    // ends early when the program waits for input or the vertical blank, halts or hits a
+    // breakpoint, and the reason is returned.
+    pub fn run_frame(&mut self) -> Result<StepResult, String> {
+        self.run_cycles(self.cycles_per_frame)
+    }
+
+    // Like `run_frame` but with an explicit instruction count, e.g. from `cycles_for_elapsed`
+    pub fn run_cycles(&mut self, cycles: u32) -> Result<StepResult, String> {
+        let mut result = StepResult::Continue;
+        for _ in 0..cycles {
+            result = self.step()?;
+            if !matches!(result, StepResult::Continue | StepResult::UnknownOpcode(_)) {
+                break;
+            }
+        }
+        self.tick_timers();
+        Ok(result)
+    }
+
+    // Run one frame and return the display, for hosts that drive the emulator themselves, e.g.
+    // from `requestAnimationFrame` in the browser. Nothing here touches SDL.
+    pub fn advance_frame(&mut self) -> Result<&[u8], String> {
+        self.run_frame()?;
+        Ok(self.display_buffer())
+    }
+
+    // Press or release key 0x0-0xF, anything outside the keypad is ignored
+    pub fn set_key(&mut self, index: usize, pressed: bool) {
+        if let Some(key) = self.keypad.get_mut(index) {
+            *key = pressed;
+        }
+    }
+
+    pub fn release_all_keys(&mut self) {
+        self.keypad = [false; 16];
+    }
+
+    // A copy of which keys are held, for on-screen keypads and checking key mappings
+    pub fn keypad_state(&self) -> [bool; 16] {
+        self.keypad
+    }
+
+    // Decode `count` instructions from `start` into address/mnemonic pairs, without executing them.
+    // F000 NNNN is four bytes long and shown with its address. Stops early at the end of memory.
+    pub fn disassemble(&self, start: u16, count: usize) -> Vec<(u16, String)> {
+        let mut listing = Vec::with_capacity(count);
+        let mut address = start as usize;
+        while listing.len() < count && address + 1 < self.memory.len() {
+            let opcode = self.read_word(address as u16);
+            let (text, length) = if opcode == 0xF000 && address + 3 < self.memory.len() {
+                let nnnn = self.read_word(address as u16 + 2);
+                (disassembler::long_load(nnnn), 4)
+            } else {
+                (disassembler::mnemonic(opcode), 2)
+            };
+            listing.push((address as u16, text));
+            address += length;
+        }
+        listing
+    }
+
+    // Trace execution, e.g. to follow the control flow of a ROM stuck in a loop.
+    // Pass `None` to remove the hook again.
+    pub fn set_instruction_hook(&mut self, hook: Option<Box<dyn FnMut(u16, u16)>>) {
+        self.on_instruction = hook;
+    }
+
+    // Watch memory accesses made by instructions, e.g. for watchpoints or a live memory view.
+    // The hooks get the address and the value read or written, pass `None` to remove them.
+    pub fn set_memory_read_hook(&mut self, hook: Option<Box<dyn FnMut(u16, u8)>>) {
+        self.on_mem_read = hook;
+    }
+
+    pub fn set_memory_write_hook(&mut self, hook: Option<Box<dyn FnMut(u16, u8)>>) {
+        self.on_mem_write = hook;
+    }
+
+    // Every data access from an instruction goes through these two so the hooks see all of them
+    fn read_memory(&mut self, address: u16) -> u8 {
+        let address = address & self.address_mask();
+        let value = self.memory[address as usize];
+        if let Some(on_mem_read) = &mut self.on_mem_read {
+            on_mem_read(address, value);
+        }
+        value
+    }
+
+    fn write_memory(&mut self, address: u16, value: u8) {
+        let address = address & self.address_mask();
+        self.memory[address as usize] = value;
+        if let Some(on_mem_write) = &mut self.on_mem_write {
+            on_mem_write(address, value);
+        }
+    }
+
+    // Whether the program has stopped with 00FD, as opposed to halting by jumping to itself
+    pub fn exited(&self) -> bool {
+        self.fetch_opcode() == 0x00FD
+    }
+
+    // Opcodes are two bytes long and stored big-endian
+    fn fetch_opcode(&self) -> u16 {
+        self.read_word(self.pc)
+    }
+
+    fn read_word(&self, address: u16) -> u16 {
+        let mask = self.address_mask();
+        let high = self.memory[(address & mask) as usize] as u16;
+        let low = self.memory[(address.wrapping_add(1) & mask) as usize] as u16;
+        high << 8 | low
+    }
+
+    // Skip over the next instruction, which is four bytes long if it's XO-CHIP's F000 NNNN
+    fn skip_instruction(&mut self) {
+        let next = self.pc.wrapping_add(2);
+        let length = if self.read_word(next) == 0xF000 { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(length);
+    }
+
     // Some Common placeholders:
     // nnn or addr - A 12-bit value, the lowest 12 bits of the instruction
     // n or nibble - A 4-bit value, the lowest 4 bits of the instruction
     // x - A 4-bit value, the lower 4 bits of the high byte of the instruction
     // y - A 4-bit value, the upper 4 bits of the low byte of the instruction
-    fn emulate_cycle(&mut self) {
-        let opcode = (self.memory[self.pc as usize] as u16) << 8
-            | self.memory[(self.pc + 1) as usize] as u16;
+    fn emulate_cycle(&mut self) -> Result<StepResult, String> {
+        let opcode = self.fetch_opcode();
+
+        if let Some(on_instruction) = &mut self.on_instruction {
+            on_instruction(self.pc, opcode);
+        }
+        if self.profile {
+            *self
+                .opcode_stats
+                .entry(disassembler::opcode_class(opcode))
+                .or_insert(0) += 1;
+        }
+
+        // Jumps, calls and returns set PC themselves, so they must skip the increment below
+        let mut advance_pc = true;
+        let mut unknown_opcode = false;
+        let mut result = StepResult::Continue;
 
         match opcode & 0xF000 {
             // 0xAnnn: Set I to nnn
             0xA000 => self.i = opcode & 0x0FFF,
-            // 0x00E0: Clear the display,
-            0x00E0 => {
-                for pixel in &mut self.display {
-                    *pixel = 0;
+            // 0x0***: System instructions, the low byte decides which one
+            // The system instructions need all 12 bits to match, e.g. 0x05E0 is a 0nnn call and not
+            // a clear screen
+            0x0000 => match opcode & 0x0FFF {
+                // 0x00E0: Clear the display
+                0x00E0 => {
+                    for pixel in &mut self.display {
+                        *pixel &= !self.planes;
+                    }
                 }
-            }
-            // 0x0nnn: Call machine language routine
-            0x0000 => {
-                // Skip the instruction because we're not emulating any machine code.
-                self.pc += 2;
-            }
+                // 0x00Cn: Scroll the display down by n pixels (SUPER-CHIP)
+                0x00C0..=0x00CF => {
+                    let n = (opcode & 0x000F) as usize;
+                    let width = self.width;
+                    self.display
+                        .copy_within(0..(self.height - n) * width, n * width);
+                    self.display[..n * width].fill(0);
+                }
+                // 0x00FB: Scroll the display right by 4 pixels (SUPER-CHIP)
+                0x00FB => {
+                    for row in self.display.chunks_mut(self.width) {
+                        let width = row.len();
+                        row.copy_within(..width - 4, 4);
+                        row[..4].fill(0);
+                    }
+                }
+                // 0x00FC: Scroll the display left by 4 pixels (SUPER-CHIP)
+                0x00FC => {
+                    for row in self.display.chunks_mut(self.width) {
+                        let width = row.len();
+                        row.copy_within(4.., 0);
+                        row[width - 4..].fill(0);
+                    }
+                }
+                // 0x00FD: Exit the interpreter (SUPER-CHIP). PC stays put, so stepping again
+                // keeps reporting the halt.
+                0x00FD => {
+                    advance_pc = false;
+                    result = StepResult::Halt;
+                }
+                // 0x00FE: Switch to 64x32 low resolution mode (SUPER-CHIP)
+                0x00FE => self.set_resolution(64, 32),
+                // 0x00FF: Switch to 128x64 high resolution mode (SUPER-CHIP)
+                0x00FF => self.set_resolution(128, 64),
+                // 0x00EE: Return from subroutine
+                0x00EE => {
+                    if self.sp == 0 {
+                        // Returning with an empty stack is ignored outside strict mode
+                        if self.strict {
+                            return Err(format!("Stack underflow at {:#05X}", self.pc));
+                        }
+                        self.stack_fault = true;
+                    } else {
+                        // A stack pointer set past the end from outside returns from the top
+                        self.sp = self.sp.min(self.stack.len()) - 1;
+                        self.pc = self.stack[self.sp];
+                        advance_pc = false;
+                    }
+                }
+                // 0x0nnn: Call machine language routine
+                // There's no machine code to run, so this is a no-op and the usual increment
+                // below moves on to the next instruction.
+                _ => {}
+            },
             // 0x1nnn: Jump to address nnn
-            0x1000 => self.pc = opcode & 0x0FFF,
+            0x1000 => {
+                let address = opcode & 0x0FFF;
+                if self.halt_on_self_jump && address == self.pc {
+                    result = StepResult::Halt;
+                }
+                self.pc = address;
+                advance_pc = false;
+            }
             // 0x2nnn: Call subroutine at nnn
             0x2000 => {
-                self.stack[self.sp] = self.pc;
-                self.sp += 1;
-                self.pc = opcode & 0x0FFF;
-            }
-            // 0x00EE: Return from subroutine
-            0x00EE => {
-                if self.sp > 0 {
-                    self.sp -= 1;
+                if self.sp >= self.stack.len() {
+                    // Calling with a full stack is ignored outside strict mode
+                    if self.strict {
+                        return Err(format!("Stack overflow at {:#05X}", self.pc));
+                    }
+                    self.stack_fault = true;
+                } else {
+                    // Push the address of the next instruction so 00EE resumes after the call
+                    self.stack[self.sp] = self.pc.wrapping_add(2);
+                    self.sp += 1;
+                    self.pc = opcode & 0x0FFF;
+                    advance_pc = false;
                 }
-                self.pc = self.stack[self.sp];
             }
             // 0x3xnn: Skip next instruction if Vx = nn
             0x3000 => {
                 let x = ((opcode & 0x0F00) >> 8) as usize;
                 let nn = (opcode & 0x00FF) as u8;
                 if self.v[x] == nn {
-                    self.pc += 2;
+                    self.skip_instruction();
                 }
             }
             // 0x4xnn: Skip next instruction if Vx != nn
@@ -148,15 +1024,35 @@ impl Chippy {
                 let x = ((opcode & 0x0F00) >> 8) as usize;
                 let nn = (opcode & 0x00FF) as u8;
                 if self.v[x] != nn {
-                    self.pc += 2;
+                    self.skip_instruction();
                 }
             }
-            // 0x5xy0: Skip next instruction if Vx = Vy
             0x5000 => {
                 let x = ((opcode & 0x0F00) >> 8) as usize;
                 let y = ((opcode & 0x00F0) >> 4) as usize;
-                if self.v[x] == self.v[y] {
-                    self.pc += 2;
+                match opcode & 0x000F {
+                    // 0x5xy0: Skip next instruction if Vx = Vy
+                    0x0 => {
+                        if self.v[x] == self.v[y] {
+                            self.skip_instruction();
+                        }
+                    }
+                    // 0x5xy2: Store Vx through Vy in memory starting at I (XO-CHIP)
+                    // Unlike Fx55/Fx65 these never move I, so the increment quirk doesn't apply.
+                    0x2 => {
+                        for (offset, register) in register_range(x, y).enumerate() {
+                            let address = self.i.wrapping_add(offset as u16);
+                            self.write_memory(address, self.v[register]);
+                        }
+                    }
+                    // 0x5xy3: Load Vx through Vy from memory starting at I (XO-CHIP)
+                    0x3 => {
+                        for (offset, register) in register_range(x, y).enumerate() {
+                            let address = self.i.wrapping_add(offset as u16);
+                            self.v[register] = self.read_memory(address);
+                        }
+                    }
+                    _ => unknown_opcode = true,
                 }
             }
             // 0x9xy0: Skip next instruction if Vx != Vy
@@ -164,7 +1060,7 @@ impl Chippy {
                 let x = ((opcode & 0x0F00) >> 8) as usize;
                 let y = ((opcode & 0x00F0) >> 4) as usize;
                 if self.v[x] != self.v[y] {
-                    self.pc += 2;
+                    self.skip_instruction();
                 }
             }
             // 0x6xnn: Set Vx = nn
@@ -185,6 +1081,8 @@ impl Chippy {
                 let y = ((opcode & 0x00F0) >> 4) as usize;
                 let n = opcode & 0x0F;
 
+                // VF can be an operand as well as the flag, so every arm reads its operands first
+                // and writes VF last. With x = 0xF the flag wins over the arithmetic result.
                 match n {
                     // 0x8xy0: Vx = Vy
                     0x0000 => self.v[x] = self.v[y],
@@ -214,86 +1112,151 @@ impl Chippy {
                     }
                     // 0x8xy6: Right shift Vx by 1, set VF = least significant bit of Vx before shift
                     0x0006 => {
-                        self.v[0xF] = self.v[x] & 0x1;
-                        self.v[x] >>= 1;
+                        let value = if self.quirks.shift_vy_into_vx {
+                            self.v[y]
+                        } else {
+                            self.v[x]
+                        };
+                        self.v[x] = value >> 1;
+                        self.v[0xF] = value & 0x1;
                     }
                     // 0x8xyE: Left shift Vx by 1, set VF = most significant bit of Vx before shift
                     0x000E => {
-                        self.v[0xF] = (self.v[x] >> 7) & 0x1;
-                        self.v[x] <<= 1;
+                        let value = if self.quirks.shift_vy_into_vx {
+                            self.v[y]
+                        } else {
+                            self.v[x]
+                        };
+                        self.v[x] = value << 1;
+                        self.v[0xF] = (value >> 7) & 0x1;
                     }
 
-                    _ => {
-                        println!("Unknown opcode: {:X}", opcode);
-                    }
+                    _ => unknown_opcode = true,
+                }
+                // The COSMAC VIP ran the logic operations through a routine that clobbers VF
+                if self.quirks.logic_resets_vf && matches!(n, 0x1..=0x3) {
+                    self.v[0xF] = 0;
                 }
             }
-            // 0xBnnn: Jump to address nnn + V0
+            // 0xBnnn: Jump to address nnn + V0 (or 0xBxnn: xnn + Vx with the SUPER-CHIP quirk)
             0xB000 => {
                 let nnn = opcode & 0x0FFF;
-                self.pc = nnn + self.v[0] as u16;
+                let base = if self.quirks.bxnn_uses_vx {
+                    ((opcode & 0x0F00) >> 8) as usize
+                } else {
+                    0
+                };
+                // Keep the target inside memory
+                self.pc = (nnn + self.v[base] as u16) & self.address_mask();
+                advance_pc = false;
             }
             // 0xCxnn: Set Vx = random byte & nn
             0xC000 => {
                 let x: usize = ((opcode & 0x0F00) >> 8) as usize;
                 let nn: u8 = (opcode & 0x00FF) as u8;
-                self.v[x] = rand::random::<u8>() & nn;
+                self.v[x] = self.rng.next_u8() & nn;
+            }
+            // 0xDxyn with display_wait: only one draw per frame, stall until the next one
+            0xD000 if self.quirks.display_wait && self.drawn_this_frame => {
+                advance_pc = false;
+                result = StepResult::WaitingForVblank;
             }
             // 0xDxyn: DISPLAY
             0xD000 => {
-                let x = self.v[((opcode & 0x0F00) >> 8) as usize] as usize % 64;
-                let y = self.v[((opcode & 0x00F0) >> 4) as usize] as usize % 32;
+                self.drawn_this_frame = true;
+                let x = self.v[((opcode & 0x0F00) >> 8) as usize] as usize % self.width;
+                let y = self.v[((opcode & 0x00F0) >> 4) as usize] as usize % self.height;
                 let n = opcode & 0x0F;
 
-                self.v[0xF] = 0; // Reset VF
+                // Dxy0 draws a 16x16 sprite made of two bytes per row (SUPER-CHIP)
+                let (rows, sprite_width) = if n == 0 { (16, 16) } else { (n, 8) };
 
-                for row in 0..n {
-                    let sprite = self.memory[(self.i + row) as usize];
-                    let mut pixel_row = sprite;
+                self.v[0xF] = 0; // Reset VF
 
-                    let mut pixel_x = x;
-                    let pixel_y = (y + row as usize) % 32;
+                // Each selected plane reads its own sprite, stored one after the other (XO-CHIP).
+                // Drawing never changes I itself, so drawing twice from the same I erases the
+                // sprite again.
+                let mut sprite_address = self.i;
+                for plane in [PLANE_1, PLANE_2] {
+                    if self.planes & plane == 0 {
+                        continue;
+                    }
 
-                    for _ in 0..8 {
-                        let pixel_value = pixel_row >> 7;
-                        let pixel_index = (pixel_y * 64 + pixel_x) as usize;
+                    for row in 0..rows {
+                        // Left-align rows in a 16-bit value so both sprite widths share the loop.
+                        // Sprite addresses wrap inside memory so a bad I can't read out of bounds.
+                        let mask = self.address_mask();
+                        let address = |offset: u16| sprite_address.wrapping_add(offset) & mask;
+                        let mut pixel_row = if sprite_width == 16 {
+                            (self.read_memory(address(row * 2)) as u16) << 8
+                                | self.read_memory(address(row * 2 + 1)) as u16
+                        } else {
+                            (self.read_memory(address(row)) as u16) << 8
+                        };
 
-                        if pixel_value == 1 {
-                            if self.display[pixel_index] != 0 {
-                                self.display[pixel_index] = 0;
-                                self.v[0xF] = 1; // Set VF if collision occurs
-                            } else {
-                                self.display[pixel_index] = 1;
+                        // The start position always wraps, but rows running off the bottom either
+                        // clip or wrap around depending on the quirk
+                        let mut pixel_y = y + row as usize;
+                        if pixel_y >= self.height {
+                            if self.quirks.clip_sprites {
+                                break;
                             }
+                            pixel_y %= self.height;
                         }
 
-                        pixel_row <<= 1;
-                        pixel_x = (pixel_x + 1) % 64;
+                        for column in 0..sprite_width {
+                            let mut pixel_x = x + column;
+                            if pixel_x >= self.width {
+                                if self.quirks.clip_sprites {
+                                    break;
+                                }
+                                pixel_x %= self.width;
+                            }
+
+                            // The leftmost pixel is the most significant bit of the row
+                            let pixel_value = pixel_row >> 15;
+                            let pixel_index = pixel_y * self.width + pixel_x;
+
+                            if pixel_value == 1 {
+                                if self.display[pixel_index] & plane != 0 {
+                                    self.v[0xF] = 1; // Set VF if collision occurs
+                                }
+                                self.display[pixel_index] ^= plane;
+                                self.metrics.pixels_toggled += 1;
+                            }
+
+                            pixel_row <<= 1;
+                        }
                     }
+
+                    sprite_address = sprite_address.wrapping_add(rows * (sprite_width as u16 / 8));
                 }
 
-                self.i += n;
+                self.metrics.draw_calls += 1;
+                if self.v[0xF] == 1 {
+                    self.metrics.collisions += 1;
+                }
             }
 
             // 0xEx**: Skip if key
             0xE000 => {
                 let x = ((opcode & 0x0F00) >> 8) as usize;
+                // Only the low nibble names a key, like Fx29 does for the font
+                let key = (self.v[x] & 0x0F) as usize;
                 match opcode & 0x00FF {
                     // 0xEx9E: Skip next instruction if key with the value of Vx is pressed
                     0x009E => {
-                        if self.keypad[self.v[x] as usize] {
-                            self.pc += 2;
+                        if self.keypad[key] {
+                            self.skip_instruction();
                         }
                     }
                     // 0xExA1: Skip next instruction if key with the value of Vx is not pressed
                     0x00A1 => {
-                        if !self.keypad[self.v[x] as usize] {
-                            self.pc += 2;
+                        if !self.keypad[key] {
+                            self.skip_instruction();
                         }
                     }
-                    _ => {
-                        println!("Unknown opcode: {:X}", opcode);
-                    }
+                    _ => unknown_opcode = true,
                 }
             }
 
@@ -301,10 +1264,31 @@ impl Chippy {
             0xF000 => {
                 let x = ((opcode & 0x0F00) >> 8) as usize;
                 match opcode & 0x00FF {
+                    // 0xFn01: Select the planes that drawing affects, n is a bitmask (XO-CHIP)
+                    0x0001 => {
+                        self.planes = x as u8 & (PLANE_1 | PLANE_2);
+                    }
+                    // 0xF000 NNNN: Set I to the 16-bit address in the next two bytes (XO-CHIP)
+                    0x0000 if x == 0 => {
+                        self.i = self.read_word(self.pc.wrapping_add(2)) & self.address_mask();
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    // 0xF002: Load the 16-byte audio pattern from memory at I (XO-CHIP)
+                    0x0002 => {
+                        let mut pattern = [0; 16];
+                        for (offset, byte) in pattern.iter_mut().enumerate() {
+                            *byte = self.read_memory(self.i.wrapping_add(offset as u16));
+                        }
+                        self.audio_pattern = Some(pattern);
+                    }
                     // 0xFx07: Set Vx = delay timer value
                     0x0007 => {
                         self.v[x] = self.delay_timer;
                     }
+                    // 0xFx3A: Set the audio pattern pitch register to Vx (XO-CHIP)
+                    0x003A => {
+                        self.pitch = self.v[x];
+                    }
                     // 0xFx15: Set delay timer = Vx
                     0x0015 => {
                         self.delay_timer = self.v[x];
@@ -315,205 +1299,231 @@ impl Chippy {
                     }
                     // 0xFX1E: Add to index
                     0x001E => {
-                        self.i += self.v[x] as u16;
+                        let sum = self.i as u32 + self.v[x] as u32;
+                        let mask = self.address_mask();
+                        if self.quirks.i_overflow_sets_vf {
+                            self.v[0xF] = (sum > mask as u32) as u8;
+                        }
+                        // Wrap around inside the address space
+                        self.i = sum as u16 & mask;
                     }
                     // 0xFx0A: Wait for a key press, store the value of the key in Vx
-                    0x000A => {
-                        let mut key_pressed = false;
-                        for i in 0..16 {
-                            if self.keypad[i] {
-                                self.v[x] = i as u8;
-                                key_pressed = true;
-                            }
+                    // The key is latched when pressed, but the instruction only completes once it
+                    // has been released again, otherwise a held key would satisfy several waits.
+                    // Only keys that went down since the last frame count, with several of them
+                    // the lowest one wins.
+                    0x000A => match self.waiting_for_key {
+                        Some(key) if !self.keypad[key] => {
+                            self.v[x] = key as u8;
+                            self.waiting_for_key = None;
                         }
-                        if !key_pressed {
-                            self.pc -= 2;
+                        Some(_) => {
+                            advance_pc = false;
+                            result = StepResult::WaitingForKey;
                         }
-                    }
+                        None => {
+                            self.waiting_for_key =
+                                (0..16).find(|&key| self.keypad[key] && !self.previous_keypad[key]);
+                            advance_pc = false;
+                            result = StepResult::WaitingForKey;
+                        }
+                    },
                     // 0xFx29: Font Character, point to the font character in memory
                     0x0029 => {
-                        let x: usize = ((opcode & 0x0F00) >> 8) as usize;
-                        let character: u8 = self.v[x];
-                        self.i = 0x50 + character as u16 * 5;
+                        // Only the low nibble names a digit, anything above 0xF would point past
+                        // the end of the font
+                        let character = self.v[x] & 0x0F;
+                        self.i = FONT_ADDRESS + character as u16 * 5;
+                    }
+                    // 0xFx30: Point I to the 8x10 font character for the digit in Vx (SUPER-CHIP)
+                    0x0030 => {
+                        let character = self.v[x] & 0x0F;
+                        self.i = BIG_FONT_ADDRESS + character as u16 * 10;
                     }
                     // 0xFx33: Store BCD representation of Vx in memory locations I, I+1, and I+2
                     0x0033 => {
-                        self.memory[self.i as usize] = self.v[x] / 100;
-                        self.memory[self.i as usize + 1] = (self.v[x] / 10) % 10;
-                        self.memory[self.i as usize + 2] = (self.v[x] % 100) % 10;
+                        let value = self.v[x];
+                        self.write_memory(self.i, value / 100);
+                        self.write_memory(self.i.wrapping_add(1), (value / 10) % 10);
+                        self.write_memory(self.i.wrapping_add(2), value % 10);
+                    }
+                    // 0xFx75: Save V0 through Vx to the RPL user flags, x is at most 7 (SUPER-CHIP)
+                    0x0075 => {
+                        let count = x.min(7) + 1;
+                        self.rpl[..count].copy_from_slice(&self.v[..count]);
+                    }
+                    // 0xFx85: Restore V0 through Vx from the RPL user flags (SUPER-CHIP)
+                    0x0085 => {
+                        let count = x.min(7) + 1;
+                        self.v[..count].copy_from_slice(&self.rpl[..count]);
                     }
                     // 0xFx55: Store registers V0 through Vx in memory starting at location I
                     0x0055 => {
                         for i in 0..=x {
-                            self.memory[self.i as usize + i] = self.v[i];
+                            self.write_memory(self.i.wrapping_add(i as u16), self.v[i]);
+                        }
+                        if self.quirks.memory_increment_i {
+                            self.i = self.i.wrapping_add(x as u16 + 1);
                         }
                     }
                     // 0xFx65: Read registers V0 through Vx from memory starting at location I
                     0x0065 => {
                         for i in 0..=x {
-                            self.v[i] = self.memory[self.i as usize + i];
+                            self.v[i] = self.read_memory(self.i.wrapping_add(i as u16));
+                        }
+                        if self.quirks.memory_increment_i {
+                            self.i = self.i.wrapping_add(x as u16 + 1);
                         }
                     }
-                    _ => {
-                        println!("Unknown opcode: {:X}", opcode);
-                    }
+                    _ => unknown_opcode = true,
                 }
             }
-            _ => {
-                println!("Unimplmented or Unknown opcode: {:X}", opcode)
+            _ => unknown_opcode = true,
+        }
+
+        if unknown_opcode {
+            // Strict mode stops on the offending instruction, otherwise it's skipped and counted
+            if self.strict {
+                return Err(format!("Unknown opcode {:04X} at {:#05X}", opcode, self.pc));
             }
+            self.unknown_opcode_count += 1;
+            result = StepResult::UnknownOpcode(opcode);
         }
 
+        if advance_pc {
+            self.pc = self.pc.wrapping_add(2);
+        }
+        Ok(result)
+    }
+
+    fn init_font(&mut self) {
+        let font = FONT_ADDRESS as usize;
+        self.memory[font..font + FONT.len()].copy_from_slice(&FONT);
+        let big_font = BIG_FONT_ADDRESS as usize;
+        self.memory[big_font..big_font + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
+    }
+
+    // Both timers count down at 60Hz, so this has to be called exactly once per frame, after its
+    // instructions have run
+    pub fn tick_timers(&mut self) {
+        // Timers tick once per frame, so this is also where the vertical blank happens
+        self.drawn_this_frame = false;
+        self.previous_keypad = self.keypad;
+        self.apply_cheats();
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
+        // Only a new beep is stretched, one that's already sounding just follows the timer
+        let minimum = if self.beep_frames == 0 {
+            self.min_beep_frames
+        } else {
+            0
+        };
+        self.beep_frames = self.beep_frames.saturating_sub(1);
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                println!("BEEP!");
+            if self.sound_timer >= self.beep_threshold {
+                // The frame that just ran counts, so a timer of n sounds for n frames
+                let frames = self.sound_timer as u32;
+                self.beep_frames = self.beep_frames.max(frames).max(minimum);
             }
             self.sound_timer -= 1;
         }
+    }
 
-        self.pc += 2;
+    // Whether the buzzer should be on for the frame that just ran
+    pub fn beeping(&self) -> bool {
+        self.beep_frames > 0
     }
+}
 
-    fn update_display(&mut self, canvas: &mut sdl2::render::Canvas<sdl2::video::Window>) {
-        // Scale the display by 20x for better visibility
-        canvas.set_scale(20.0, 20.0).unwrap();
+fn read_rom<R: Read>(mut reader: R) -> Result<Vec<u8>, String> {
+    let mut rom = Vec::new();
+    reader
+        .read_to_end(&mut rom)
+        .map_err(|e| format!("Failed to read ROM: {}", e))?;
+    Ok(rom)
+}
 
-        // Draw the display
-        for (i, &pixel) in self.display.iter().enumerate() {
-            let x = (i % 64) as i32;
-            let y = (i / 64) as i32;
-            if pixel == 1 {
-                canvas.set_draw_color(Color::RGB(255, 255, 255));
-            } else {
-                canvas.set_draw_color(Color::RGB(0, 0, 0));
-            }
-            canvas.draw_point((x, y)).unwrap();
-        }
+// The bytes of the ROM at `game_path`, or stdin for "-", without loading it or its profile
+#[cfg(feature = "sdl")]
+pub fn read_game(game_path: &str) -> Result<Vec<u8>, String> {
+    let rom = if game_path == "-" {
+        read_rom(std::io::stdin().lock())?
+    } else {
+        fs::read(game_path).map_err(|e| format!("{}: {}", game_path, e))?
+    };
+    // ROM archives are often gzipped, those are unpacked transparently
+    if game_path.ends_with(".gz") || gzip::is_gzip(&rom) {
+        gzip::decompress(&rom).map_err(|e| format!("{}: {}", game_path, e))
+    } else {
+        Ok(rom)
     }
+}
 
-    fn init_font(&mut self) {
-        let characters = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
-
-        for (i, &character) in characters.iter().enumerate() {
-            self.memory[0x50 + i] = character;
-        }
-    }
-
-    fn play_sound(&mut self) {
-        // Decrement the sound timer
-        if self.sound_timer > 0 {
-            self.sound_timer -= 1;
+// Reasons `rom` might not be a CHIP-8 program at all. These are only guesses, plenty of real
+// ROMs end in an odd byte of sprite data, so they are shown as warnings rather than refused.
+pub fn rom_warnings(rom: &[u8]) -> Vec<String> {
+    if let Some((_, kind)) = FILE_SIGNATURES
+        .iter()
+        .find(|(signature, _)| rom.starts_with(signature))
+    {
+        return vec![format!("looks like {}, not a CHIP-8 ROM", kind)];
+    }
+
+    let mut warnings = Vec::new();
+    if rom.len() % 2 == 1 {
+        warnings.push(format!(
+            "odd length of {} bytes, the file may be truncated",
+            rom.len()
+        ));
+    }
+    if let [high, low, ..] = *rom {
+        let opcode = u16::from_be_bytes([high, low]);
+        if disassembler::mnemonic(opcode).starts_with("DW") {
+            warnings.push(format!(
+                "first instruction {:04X} is not a valid opcode",
+                opcode
+            ));
         }
+    }
+    warnings
+}
 
-        // Play sound if the sound timer is nonzero
-        if self.sound_timer > 0 {
-            self.audio_device.resume();
-        } else {
-            self.audio_device.pause();
-        }
-    }
-
-    fn map_keycode_to_chip8_key(&self, keycode: Keycode) -> Option<usize> {
-        match keycode {
-            Keycode::Num1 => Some(0x1),
-            Keycode::Num2 => Some(0x2),
-            Keycode::Num3 => Some(0x3),
-            Keycode::Num4 => Some(0xC),
-            Keycode::Q => Some(0x4),
-            Keycode::W => Some(0x5),
-            Keycode::E => Some(0x6),
-            Keycode::R => Some(0xD),
-            Keycode::A => Some(0x7),
-            Keycode::S => Some(0x8),
-            Keycode::D => Some(0x9),
-            Keycode::F => Some(0xE),
-            Keycode::Z => Some(0xA),
-            Keycode::X => Some(0x0),
-            Keycode::C => Some(0xB),
-            Keycode::V => Some(0xF),
-            _ => None,
-        }
-    }
-
-    //  Implemented from https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/window-properties.rs
-    pub fn run(&mut self, game_path: &str) -> Result<(), String> {
-        self.init_font();
-        self.load_game(game_path)?;
-        let sdl_context = sdl2::init()?;
-        let video_subsystem = sdl_context.video()?;
-
-        let window = video_subsystem
-            .window("Chippy", 1280, 640)
-            .resizable()
-            .build()
-            .map_err(|e| e.to_string())?;
-
-        let mut canvas = window
-            .into_canvas()
-            .present_vsync()
-            .build()
-            .map_err(|e| e.to_string())?;
-
-        let mut event_pump = sdl_context.event_pump().map_err(|e| e.to_string())?;
-
-        'running: loop {
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        // Exit the application when escape is pressed
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => break 'running,
-                    Event::KeyDown {
-                        keycode: Some(keycode),
-                        repeat: false,
-                        ..
-                    } => {
-                        if let Some(index) = self.map_keycode_to_chip8_key(keycode) {
-                            self.keypad[index] = true;
-                        }
-                    }
-                    // Handle key release events
-                    Event::KeyUp {
-                        keycode: Some(keycode),
-                        ..
-                    } => {
-                        if let Some(index) = self.map_keycode_to_chip8_key(keycode) {
-                            self.keypad[index] = false;
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            canvas.set_draw_color(Color::RGB(0, 0, 0));
-            canvas.clear();
-            self.emulate_cycle();
-            self.play_sound();
-            self.update_display(&mut canvas);
-            canvas.present();
+// Registers Vx through Vy in order, which runs backwards when x > y
+fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+// Cursor over a save state that errors instead of panicking when the data runs out
+struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < count {
+            return Err("Save state is truncated".to_string());
         }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
 
-        Ok(())
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
 }
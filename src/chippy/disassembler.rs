@@ -0,0 +1,98 @@
+// Decodes opcodes into the mnemonics from Cowgod's technical reference
+// (http://devernay.free.fr/hacks/chip8/C8TECH10.HTM), plus the SUPER-CHIP and XO-CHIP extensions.
+// The dispatch mirrors `Chippy::emulate_cycle`, anything it doesn't know is shown as a data word.
+pub fn mnemonic(opcode: u16) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    match opcode & 0xF000 {
+        0x0000 => match nnn {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            0x00C0..=0x00CF => format!("SCD {}", n),
+            0x00FB => "SCR".to_string(),
+            0x00FC => "SCL".to_string(),
+            0x00FD => "EXIT".to_string(),
+            0x00FE => "LOW".to_string(),
+            0x00FF => "HIGH".to_string(),
+            _ => format!("SYS 0x{:03X}", nnn),
+        },
+        0x1000 => format!("JP 0x{:03X}", nnn),
+        0x2000 => format!("CALL 0x{:03X}", nnn),
+        0x3000 => format!("SE V{:X}, 0x{:02X}", x, nn),
+        0x4000 => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        0x5000 if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x5000 if n == 2 => format!("LD [I], V{:X}-V{:X}", x, y),
+        0x5000 if n == 3 => format!("LD V{:X}-V{:X}, [I]", x, y),
+        0x6000 => format!("LD V{:X}, 0x{:02X}", x, nn),
+        0x7000 => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        0x8000 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => data_word(opcode),
+        },
+        0x9000 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, 0x{:03X}", nnn),
+        0xB000 => format!("JP V0, 0x{:03X}", nnn),
+        0xC000 => format!("RND V{:X}, 0x{:02X}", x, nn),
+        0xD000 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000 => match nn {
+            0x009E => format!("SKP V{:X}", x),
+            0x00A1 => format!("SKNP V{:X}", x),
+            _ => data_word(opcode),
+        },
+        0xF000 => match nn {
+            0x0000 if x == 0 => "LD I, LONG".to_string(),
+            0x0001 => format!("PLANE {}", x),
+            // The interpreter ignores x here, e.g. F102 runs as AUDIO too
+            0x0002 => "AUDIO".to_string(),
+            0x0007 => format!("LD V{:X}, DT", x),
+            0x000A => format!("LD V{:X}, K", x),
+            0x0015 => format!("LD DT, V{:X}", x),
+            0x0018 => format!("LD ST, V{:X}", x),
+            0x001E => format!("ADD I, V{:X}", x),
+            0x0029 => format!("LD F, V{:X}", x),
+            0x0030 => format!("LD HF, V{:X}", x),
+            0x0033 => format!("LD B, V{:X}", x),
+            0x003A => format!("PITCH V{:X}", x),
+            0x0055 => format!("LD [I], V{:X}", x),
+            0x0065 => format!("LD V{:X}, [I]", x),
+            0x0075 => format!("LD R, V{:X}", x),
+            0x0085 => format!("LD V{:X}, R", x),
+            _ => data_word(opcode),
+        },
+        _ => data_word(opcode),
+    }
+}
+
+// F000 NNNN with the address from the word after the opcode, which `mnemonic` can't see
+pub fn long_load(nnnn: u16) -> String {
+    format!("LD I, LONG 0x{:04X}", nnnn)
+}
+
+// The instruction an opcode belongs to with its operands masked out, e.g. 0x8124 becomes 0x8004
+// and 0xF265 becomes 0xF065. Used to group executed instructions when profiling.
+pub fn opcode_class(opcode: u16) -> u16 {
+    match opcode & 0xF000 {
+        0x0000 if opcode & 0xFFF0 == 0x00C0 => 0x00C0,
+        // 00E0, 00EE and the other system instructions are told apart by their low byte
+        0x0000 if opcode & 0xFF00 == 0 => opcode,
+        0x5000 | 0x8000 | 0x9000 => opcode & 0xF00F,
+        0xE000 | 0xF000 => opcode & 0xF0FF,
+        _ => opcode & 0xF000,
+    }
+}
+
+fn data_word(opcode: u16) -> String {
+    format!("DW 0x{:04X}", opcode)
+}
@@ -0,0 +1,1161 @@
+use sdl2::{
+    audio::{AudioDevice, AudioSpecDesired, AudioStatus},
+    controller::{Button, GameController},
+    event::{Event, WindowEvent},
+    keyboard::Keycode,
+    mouse::MouseButton,
+    pixels::{Color, PixelFormatEnum},
+    rect::{Point, Rect},
+    render::{Canvas, Texture},
+    video::Window,
+    EventPump, GameControllerSubsystem, Sdl, VideoSubsystem,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::audio::{SampleRing, SharedF32, Square};
+use super::buzzer::{self, AudioBackend};
+use super::chippy::{read_game, Chippy, StepResult, FONT};
+use super::gif::{self, GifRecorder, GifWriter};
+use super::png;
+use super::replay::{InputRecorder, InputReplay};
+use super::wav::WavWriter;
+
+// Colours for each combination of the two planes: none, plane 1, plane 2 and both. Plain
+// CHIP-8 only ever draws to plane 1, so it stays black and white.
+pub const DEFAULT_PALETTE: [Color; 4] = [
+    Color::RGB(0, 0, 0),
+    Color::RGB(255, 255, 255),
+    Color::RGB(255, 102, 0),
+    Color::RGB(0, 170, 255),
+];
+
+// Everything that talks to SDL: the window, audio and input. The emulator core in `Chippy`
+// doesn't depend on any of it, so it can be constructed and tested without a display server.
+pub struct Frontend {
+    canvas: Canvas<Window>,
+    // Colours for each combination of the planes, indexed by a pixel's plane bits.
+    // ROM profiles can change them.
+    pub palette: [Color; 4],
+    event_pump: EventPump,
+    controller_subsystem: GameControllerSubsystem,
+
+    // Audio handling through SDL
+    // None when no audio device could be opened, the emulator then runs silently
+    audio_device: Option<AudioDevice<Square>>,
+    tone: SharedF32,
+    volume: f32,
+    muted: bool,
+    audio_volume: SharedF32,
+
+    // Samples queued by the audio thread and the file they're written to while recording
+    recording: Option<(SampleRing, WavWriter)>,
+    recorded: Vec<f32>,
+
+    // Keyboard bindings for the keypad
+    key_mapping: HashMap<Keycode, usize>,
+
+    // Game controller bindings for the keypad, the same keypad is shared with the keyboard
+    controller_mapping: HashMap<Button, usize>,
+
+    // Keys and buttons currently held down, the keypad is worked out from these every frame
+    held_keys: HashSet<Keycode>,
+    held_buttons: HashSet<Button>,
+
+    // On-screen keypad that shows the held keys and can be clicked, see `keypad_overlay_keys`
+    pub show_keypad: bool,
+    clicked_key: Option<usize>,
+
+    // Outline every CHIP-8 pixel, for spotting sprites drawn one pixel off, see `grid_lines`
+    pub show_grid: bool,
+
+    // Keep the display at 2:1 and letterbox it when the window has a different shape
+    pub maintain_aspect_ratio: bool,
+
+    // Reload the ROM whenever its modification time changes, for iterating on CHIP-8 programs
+    pub watch: bool,
+
+    // Phosphor-style fading of pixels that turn off, `ghost` holds the blended RGB channels
+    pub ghosting: bool,
+    ghost: Vec<f32>,
+
+    // Frames per second the run loop is paced at, independent of the monitor's refresh rate.
+    // The timers tick once per frame, so anything but 60 changes the game speed too.
+    pub target_fps: u32,
+
+    // Holding Tab runs this many times as many instructions per frame, to skip through slow
+    // intros. The timers still tick once per frame, so the buzzer's pitch doesn't change.
+    pub turbo_factor: u32,
+
+    // Each CHIP-8 pixel becomes a square this many pixels wide in screenshots and recordings
+    pub screenshot_scale: usize,
+
+    // Gameplay being recorded to a GIF, along with the resolution it was started at
+    gif_recording: Option<(GifRecorder, usize, usize)>,
+
+    // Keypad input being recorded, or replayed instead of the live input
+    input_recording: Option<InputRecorder<BufWriter<File>>>,
+    input_replay: Option<InputReplay>,
+}
+
+// Half of full scale, the raw square wave is harsh at full volume
+const DEFAULT_VOLUME: f32 = 0.5;
+
+// Range the speed hotkeys can move `cycles_per_frame` through
+const MIN_CYCLES_PER_FRAME: u32 = 1;
+const MAX_CYCLES_PER_FRAME: u32 = 1000;
+
+const DEFAULT_TURBO_FACTOR: u32 = 4;
+
+// The on-screen keypad uses the COSMAC VIP layout, the same one the keyboard is mapped to:
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+// Each key is a KEYPAD_KEY_SIZE square hitbox with KEYPAD_GAP between keys, and the whole pad
+// sits KEYPAD_MARGIN from the bottom right corner of the window
+const KEYPAD_LAYOUT: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+const KEYPAD_KEY_SIZE: u32 = 40;
+const KEYPAD_GAP: u32 = 4;
+const KEYPAD_MARGIN: u32 = 10;
+
+// Lines between the display's pixels, dim enough to not hide what's drawn
+const GRID_COLOR: Color = Color::RGB(48, 48, 48);
+
+// Lit pixels show their colour immediately, pixels that turned off keep this fraction of their
+// distance from the background every frame
+const PHOSPHOR_DECAY: f32 = 0.6;
+
+// Whether the canvas waits for the monitor's vertical blank before presenting. The run loop
+// paces itself with `frame_sleep` either way, vsync only avoids tearing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vsync {
+    // Use vsync, falling back to presenting immediately if the driver can't do it
+    Auto,
+    On,
+    Off,
+}
+
+impl Vsync {
+    // The vsync settings to try creating the canvas with, in order
+    pub fn attempts(self) -> &'static [bool] {
+        match self {
+            Vsync::Auto => &[true, false],
+            Vsync::On => &[true],
+            Vsync::Off => &[false],
+        }
+    }
+}
+
+impl FromStr for Vsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Vsync, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Vsync::Auto),
+            "on" => Ok(Vsync::On),
+            "off" => Ok(Vsync::Off),
+            _ => Err("expected auto, on or off".to_string()),
+        }
+    }
+}
+
+impl Frontend {
+    pub fn new() -> Result<Frontend, String> {
+        Frontend::with_vsync(Vsync::Auto)
+    }
+
+    pub fn with_vsync(vsync: Vsync) -> Result<Frontend, String> {
+        let sdl_context = sdl2::init()?;
+
+        let tone = SharedF32::new(440.0);
+        let audio_volume = SharedF32::new(DEFAULT_VOLUME);
+        // Servers and CI machines often have no sound card, that shouldn't stop the emulator
+        let audio_device = match Frontend::open_audio(&sdl_context, &tone, &audio_volume) {
+            Ok(audio_device) => Some(audio_device),
+            Err(e) => {
+                eprintln!("Warning: no audio, continuing without sound: {}", e);
+                None
+            }
+        };
+
+        // Some drivers can't build a vsync renderer, building the canvas consumes the window
+        // so every attempt starts from a new one
+        let video_subsystem = sdl_context.video()?;
+        let canvas = vsync
+            .attempts()
+            .iter()
+            .find_map(|&present_vsync| {
+                let with = if present_vsync { "with" } else { "without" };
+                match Frontend::create_canvas(&video_subsystem, present_vsync) {
+                    Ok(canvas) => {
+                        println!("Rendering {} vsync", with);
+                        Some(canvas)
+                    }
+                    Err(e) => {
+                        eprintln!("Warning: can't render {} vsync: {}", with, e);
+                        None
+                    }
+                }
+            })
+            .ok_or("Failed to create a renderer")?;
+
+        let controller_subsystem = sdl_context.game_controller()?;
+        let event_pump = sdl_context.event_pump()?;
+
+        Ok(Frontend {
+            canvas,
+            palette: DEFAULT_PALETTE,
+            event_pump,
+            controller_subsystem,
+            audio_device,
+            tone,
+            volume: DEFAULT_VOLUME,
+            muted: false,
+            audio_volume,
+            recording: None,
+            recorded: Vec::new(),
+            key_mapping: Frontend::default_key_mapping(),
+            controller_mapping: Frontend::default_controller_mapping(),
+            held_keys: HashSet::new(),
+            held_buttons: HashSet::new(),
+            show_keypad: false,
+            clicked_key: None,
+            show_grid: false,
+            maintain_aspect_ratio: true,
+            watch: false,
+            ghosting: false,
+            ghost: Vec::new(),
+            target_fps: 60,
+            turbo_factor: DEFAULT_TURBO_FACTOR,
+            screenshot_scale: 10,
+            gif_recording: None,
+            input_recording: None,
+            input_replay: None,
+        })
+    }
+
+    fn create_canvas(
+        video_subsystem: &VideoSubsystem,
+        present_vsync: bool,
+    ) -> Result<Canvas<Window>, String> {
+        let window = video_subsystem
+            .window("Chippy", 1280, 640)
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let mut builder = window.into_canvas();
+        if present_vsync {
+            builder = builder.present_vsync();
+        }
+        builder.build().map_err(|e| e.to_string())
+    }
+
+    fn open_audio(
+        sdl_context: &Sdl,
+        tone: &SharedF32,
+        volume: &SharedF32,
+    ) -> Result<AudioDevice<Square>, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100), // Hz
+            channels: Some(1),
+            samples: None,
+        };
+        audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            // Initialize the square wave for audio
+            Square {
+                phase_inc: tone.get() / spec.freq as f32,
+                phase: 0.0,
+                tone: tone.clone(),
+                sample_rate: spec.freq as f32,
+                volume: volume.clone(),
+                pattern: None,
+                pattern_rate: 4000.0,
+                pattern_position: 0.0,
+                recording: None,
+            }
+        })
+    }
+
+    fn pause_audio(&mut self) {
+        if let Some(audio_device) = &mut self.audio_device {
+            audio_device.beep(false);
+        }
+    }
+
+    pub fn tone(&self) -> f32 {
+        self.tone.get()
+    }
+
+    // Change the buzzer pitch in Hz, the audio thread picks it up on its next block
+    pub fn set_tone(&mut self, hz: f32) {
+        self.tone.set(hz);
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    // Set the buzzer volume from 0.0 (silent) to 1.0 (full scale), values outside are clamped
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.update_audio_volume();
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        self.update_audio_volume();
+    }
+
+    // The gain the audio thread plays at. Muting keeps `volume` so unmuting restores it.
+    pub fn output_volume(volume: f32, muted: bool) -> f32 {
+        if muted {
+            0.0
+        } else {
+            volume
+        }
+    }
+
+    fn update_audio_volume(&self) {
+        self.audio_volume
+            .set(Frontend::output_volume(self.volume, self.muted));
+    }
+
+    // Record everything the buzzer plays to a WAV file until `stop_recording` or the end of `run`
+    pub fn start_recording(&mut self, path: &str) -> Result<(), String> {
+        self.stop_recording()?;
+        let Some(audio_device) = &mut self.audio_device else {
+            return Err("No audio device to record from".to_string());
+        };
+        let sample_rate = audio_device.spec().freq as u32;
+        let writer = WavWriter::create(path, sample_rate)?;
+        // A second of audio, far more than can pile up between two frames
+        let ring = SampleRing::new(sample_rate as usize);
+        audio_device.lock().recording = Some(ring.clone());
+        self.recording = Some((ring, writer));
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<(), String> {
+        if let Some(audio_device) = &mut self.audio_device {
+            audio_device.lock().recording = None;
+        }
+        self.drain_recording()?;
+        match self.recording.take() {
+            Some((_, writer)) => writer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // Move the queued samples into the file. The audio callback doesn't run while the device is
+    // paused, so the silence in between beeps is filled in here, a frame at a time.
+    fn drain_recording(&mut self) -> Result<(), String> {
+        let (Some((ring, writer)), Some(audio_device)) = (&mut self.recording, &self.audio_device)
+        else {
+            return Ok(());
+        };
+        self.recorded.clear();
+        ring.drain(&mut self.recorded);
+        if audio_device.status() == AudioStatus::Paused {
+            let frame = audio_device.spec().freq as usize / self.target_fps.max(1) as usize;
+            self.recorded.resize(self.recorded.len() + frame, 0.0);
+        }
+        writer.write_samples(&self.recorded)
+    }
+
+    // The colour of a display pixel, plane 1 is bit 0 and plane 2 bit 1
+    pub fn pixel_color(palette: &[Color; 4], pixel: u8) -> Color {
+        palette[(pixel & 0b11) as usize]
+    }
+
+    // Convert a display buffer into RGB24 pixels, three bytes per CHIP-8 pixel. This is what the
+    // streaming texture is filled with every frame.
+    pub fn display_to_rgb24(palette: &[Color; 4], display: &[u8], pixels: &mut [u8]) {
+        for (rgb, &pixel) in pixels.chunks_exact_mut(3).zip(display.iter()) {
+            let color = Frontend::pixel_color(palette, pixel);
+            rgb.copy_from_slice(&[color.r, color.g, color.b]);
+        }
+    }
+
+    pub fn render_rgb24(&self, chippy: &Chippy, pixels: &mut [u8]) {
+        Frontend::display_to_rgb24(&self.palette, &chippy.display, pixels);
+    }
+
+    // Save the display as a PNG, scaled up by `screenshot_scale` without any smoothing
+    pub fn screenshot_png(&self, chippy: &Chippy, path: &str) -> Result<(), String> {
+        let scale = self.screenshot_scale.max(1);
+        let mut pixels = vec![0; chippy.width() * chippy.height() * 3];
+        self.render_rgb24(chippy, &mut pixels);
+
+        let row_size = chippy.width() * scale * 3;
+        let mut scaled = Vec::with_capacity(row_size * chippy.height() * scale);
+        for row in pixels.chunks_exact(chippy.width() * 3) {
+            let start = scaled.len();
+            for rgb in row.chunks_exact(3) {
+                for _ in 0..scale {
+                    scaled.extend_from_slice(rgb);
+                }
+            }
+            for _ in 1..scale {
+                scaled.extend_from_within(start..start + row_size);
+            }
+        }
+
+        png::write_png(
+            path,
+            (chippy.width() * scale) as u32,
+            (chippy.height() * scale) as u32,
+            &scaled,
+        )
+    }
+
+    // Record every rendered frame to a GIF until `stop_gif_recording` or the end of `run`
+    pub fn start_gif_recording(&mut self, chippy: &Chippy, path: &str) -> Result<(), String> {
+        self.stop_gif_recording()?;
+        let scale = self.screenshot_scale.max(1);
+        let palette = self.palette.map(|color| [color.r, color.g, color.b]);
+        let writer = GifWriter::create(
+            path,
+            (chippy.width() * scale) as u16,
+            (chippy.height() * scale) as u16,
+            &palette,
+        )?;
+        self.gif_recording = Some((GifRecorder::new(writer), chippy.width(), chippy.height()));
+        Ok(())
+    }
+
+    pub fn stop_gif_recording(&mut self) -> Result<(), String> {
+        match self.gif_recording.take() {
+            Some((recorder, _, _)) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn record_gif_frame(&mut self, chippy: &Chippy) -> Result<(), String> {
+        let Some((recorder, width, height)) = &mut self.gif_recording else {
+            return Ok(());
+        };
+        // Every frame of a GIF has the same size, so a resolution switch ends the recording
+        if (*width, *height) != (chippy.width(), chippy.height()) {
+            println!("Resolution changed, stopped recording");
+            return self.stop_gif_recording();
+        }
+        let indices = gif::scale_indices(
+            &gif::frame_indices(&chippy.display),
+            chippy.width(),
+            self.screenshot_scale.max(1),
+        );
+        recorder.add_frame(indices, 1.0 / self.target_fps.max(1) as f32)
+    }
+
+    // Record the keypad of every frame from the start of `run`, so the session can be replayed
+    // exactly with `play`. The machine is seeded from the recording, overriding any other seed.
+    pub fn start_record(&mut self, path: &str) -> Result<(), String> {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        self.input_recording = Some(InputRecorder::create(path, seed)?);
+        Ok(())
+    }
+
+    // Replay a recording made with `start_record` from the start of `run`. Live input is ignored
+    // until it runs out.
+    pub fn play(&mut self, path: &str) -> Result<(), String> {
+        self.input_replay = Some(InputReplay::open(path)?);
+        Ok(())
+    }
+
+    // One frame of ghosting for a single colour channel: a lit pixel jumps to `target`, a pixel
+    // that's off decays from `previous` towards it
+    pub fn fade(previous: f32, target: f32, lit: bool) -> f32 {
+        if lit {
+            target
+        } else {
+            target + (previous - target) * PHOSPHOR_DECAY
+        }
+    }
+
+    // Blend pixels that turned off towards the background over several frames instead of
+    // clearing them at once, which hides most of the XOR flicker. `display` isn't touched.
+    fn apply_ghosting(&mut self, chippy: &Chippy, pixels: &mut [u8]) {
+        if self.ghost.len() != pixels.len() {
+            self.ghost = pixels.iter().map(|&channel| channel as f32).collect();
+        }
+        for ((rgb, ghost), &pixel) in pixels
+            .chunks_exact_mut(3)
+            .zip(self.ghost.chunks_exact_mut(3))
+            .zip(chippy.display.iter())
+        {
+            for (channel, brightness) in rgb.iter_mut().zip(ghost) {
+                *brightness = Frontend::fade(*brightness, *channel as f32, pixel != 0);
+                *channel = brightness.round() as u8;
+            }
+        }
+    }
+
+    fn update_display(
+        &mut self,
+        chippy: &Chippy,
+        texture: &mut Texture,
+        pixels: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        let (width, height) = (chippy.width(), chippy.height());
+        pixels.resize(width * height * 3, 0);
+        self.render_rgb24(chippy, pixels);
+        if self.ghosting {
+            self.apply_ghosting(chippy, pixels);
+        }
+        texture
+            .update(None, pixels, width * 3)
+            .map_err(|e| e.to_string())?;
+
+        // Either stretch the texture over the whole window, or scale it as large as it fits
+        // while keeping the 2:1 aspect ratio and letterbox the rest
+        let (window_width, window_height) = self.canvas.output_size()?;
+        let destination = if self.maintain_aspect_ratio {
+            let scale = f32::min(
+                window_width as f32 / width as f32,
+                window_height as f32 / height as f32,
+            );
+            let scaled_width = (width as f32 * scale) as u32;
+            let scaled_height = (height as f32 * scale) as u32;
+            Rect::new(
+                ((window_width - scaled_width) / 2) as i32,
+                ((window_height - scaled_height) / 2) as i32,
+                scaled_width,
+                scaled_height,
+            )
+        } else {
+            Rect::new(0, 0, window_width, window_height)
+        };
+        self.canvas.copy(texture, None, destination)?;
+
+        // Drawn on the canvas only, the machine's display is untouched
+        let lines = Frontend::grid_lines(self.show_grid, destination, width, height);
+        if !lines.is_empty() {
+            self.canvas.set_draw_color(GRID_COLOR);
+            for (start, end) in lines {
+                self.canvas.draw_line(start, end)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The lines between the pixels of a `width` x `height` display drawn into `destination`,
+    // none when the grid is off
+    pub fn grid_lines(
+        show_grid: bool,
+        destination: Rect,
+        width: usize,
+        height: usize,
+    ) -> Vec<(Point, Point)> {
+        if !show_grid {
+            return Vec::new();
+        }
+        let (left, top) = (destination.left(), destination.top());
+        let (right, bottom) = (destination.right() - 1, destination.bottom() - 1);
+        let columns = (1..width).map(|column| {
+            let x = left + (column as u64 * destination.width() as u64 / width as u64) as i32;
+            (Point::new(x, top), Point::new(x, bottom))
+        });
+        let rows = (1..height).map(|row| {
+            let y = top + (row as u64 * destination.height() as u64 / height as u64) as i32;
+            (Point::new(left, y), Point::new(right, y))
+        });
+        columns.chain(rows).collect()
+    }
+
+    // The keys of the on-screen keypad and where they are drawn in a window of the given size
+    pub fn keypad_overlay_keys(window_width: u32, window_height: u32) -> Vec<(usize, Rect)> {
+        let step = (KEYPAD_KEY_SIZE + KEYPAD_GAP) as i32;
+        let size = 4 * step - KEYPAD_GAP as i32;
+        let left = window_width as i32 - KEYPAD_MARGIN as i32 - size;
+        let top = window_height as i32 - KEYPAD_MARGIN as i32 - size;
+        KEYPAD_LAYOUT
+            .iter()
+            .enumerate()
+            .map(|(position, &key)| {
+                let (column, row) = ((position % 4) as i32, (position / 4) as i32);
+                let rect = Rect::new(
+                    left + column * step,
+                    top + row * step,
+                    KEYPAD_KEY_SIZE,
+                    KEYPAD_KEY_SIZE,
+                );
+                (key, rect)
+            })
+            .collect()
+    }
+
+    // The on-screen key under a click at (x, y), if any
+    pub fn keypad_key_at(window_width: u32, window_height: u32, x: i32, y: i32) -> Option<usize> {
+        Frontend::keypad_overlay_keys(window_width, window_height)
+            .into_iter()
+            .find(|(_, rect)| rect.contains_point((x, y)))
+            .map(|(key, _)| key)
+    }
+
+    // Draw the on-screen keypad over the display, held keys are filled in. The labels are the
+    // glyphs of the CHIP-8 font itself.
+    fn draw_keypad(&mut self, chippy: &Chippy) -> Result<(), String> {
+        let (window_width, window_height) = self.canvas.output_size()?;
+        let held = chippy.keypad_state();
+        let scale = KEYPAD_KEY_SIZE as i32 / 8;
+        for (key, rect) in Frontend::keypad_overlay_keys(window_width, window_height) {
+            let (background, foreground) = if held[key] {
+                (self.palette[1], self.palette[0])
+            } else {
+                (self.palette[0], self.palette[1])
+            };
+            self.canvas.set_draw_color(background);
+            self.canvas.fill_rect(rect)?;
+            self.canvas.set_draw_color(self.palette[1]);
+            self.canvas.draw_rect(rect)?;
+
+            self.canvas.set_draw_color(foreground);
+            let left = rect.x() + (KEYPAD_KEY_SIZE as i32 - 4 * scale) / 2;
+            let top = rect.y() + (KEYPAD_KEY_SIZE as i32 - 5 * scale) / 2;
+            for (row, &bits) in FONT[key * 5..key * 5 + 5].iter().enumerate() {
+                for column in 0..4 {
+                    if bits & (0x80 >> column) != 0 {
+                        self.canvas.fill_rect(Rect::new(
+                            left + column * scale,
+                            top + row as i32 * scale,
+                            scale as u32,
+                            scale as u32,
+                        ))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn play_sound(&mut self, chippy: &Chippy) {
+        if let Some(audio_device) = &mut self.audio_device {
+            buzzer::play_sound(audio_device, chippy);
+        }
+    }
+
+    // The CHIP-8 keypad laid out on the left side of a QWERTY keyboard:
+    // 1 2 3 C      1 2 3 4
+    // 4 5 6 D  ->  Q W E R
+    // 7 8 9 E      A S D F
+    // A 0 B F      Z X C V
+    pub fn default_key_mapping() -> HashMap<Keycode, usize> {
+        HashMap::from([
+            (Keycode::Num1, 0x1),
+            (Keycode::Num2, 0x2),
+            (Keycode::Num3, 0x3),
+            (Keycode::Num4, 0xC),
+            (Keycode::Q, 0x4),
+            (Keycode::W, 0x5),
+            (Keycode::E, 0x6),
+            (Keycode::R, 0xD),
+            (Keycode::A, 0x7),
+            (Keycode::S, 0x8),
+            (Keycode::D, 0x9),
+            (Keycode::F, 0xE),
+            (Keycode::Z, 0xA),
+            (Keycode::X, 0x0),
+            (Keycode::C, 0xB),
+            (Keycode::V, 0xF),
+        ])
+    }
+
+    // Replace the keyboard bindings, e.g. for AZERTY or Dvorak layouts
+    pub fn set_key_mapping(&mut self, mapping: HashMap<Keycode, usize>) -> Result<(), String> {
+        Frontend::check_key_mapping(&mapping)?;
+        self.key_mapping = mapping;
+        Ok(())
+    }
+
+    pub fn check_key_mapping(mapping: &HashMap<Keycode, usize>) -> Result<(), String> {
+        match mapping.iter().find(|(_, &index)| index >= 16) {
+            Some((keycode, index)) => Err(format!(
+                "Invalid CHIP-8 key {:#X} for {}, keys range from 0x0 to 0xF",
+                index, keycode
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Derive the keypad from the keys and buttons being held
+    fn update_keypad(&self, chippy: &mut Chippy) {
+        let keys = self
+            .held_keys
+            .iter()
+            .filter_map(|&keycode| Frontend::map_keycode_to_chip8_key(&self.key_mapping, keycode));
+        let buttons = self
+            .held_buttons
+            .iter()
+            .filter_map(|&button| self.map_button_to_chip8_key(button));
+        chippy.release_all_keys();
+        for index in keys.chain(buttons).chain(self.clicked_key) {
+            chippy.set_key(index, true);
+        }
+    }
+
+    pub fn map_keycode_to_chip8_key(
+        mapping: &HashMap<Keycode, usize>,
+        keycode: Keycode,
+    ) -> Option<usize> {
+        mapping.get(&keycode).copied()
+    }
+
+    // The D-pad moves like WASD on the keyboard layout above, the buttons cover the keys
+    // around it that games commonly use for actions:
+    // D-pad up/left/down/right -> 5 7 8 9 (W A S D)
+    // A -> 6 (E)   B -> 4 (Q)   X -> A (Z)   Y -> C (4)
+    // Start -> F (V)   Back -> 0 (X)
+    pub fn default_controller_mapping() -> HashMap<Button, usize> {
+        HashMap::from([
+            (Button::DPadUp, 0x5),
+            (Button::DPadLeft, 0x7),
+            (Button::DPadDown, 0x8),
+            (Button::DPadRight, 0x9),
+            (Button::A, 0x6),
+            (Button::B, 0x4),
+            (Button::X, 0xA),
+            (Button::Y, 0xC),
+            (Button::Start, 0xF),
+            (Button::Back, 0x0),
+        ])
+    }
+
+    // Replace the controller bindings
+    pub fn set_controller_mapping(
+        &mut self,
+        mapping: HashMap<Button, usize>,
+    ) -> Result<(), String> {
+        if let Some((button, index)) = mapping.iter().find(|(_, &index)| index >= 16) {
+            return Err(format!(
+                "Invalid CHIP-8 key {:#X} for {}, keys range from 0x0 to 0xF",
+                index,
+                button.string()
+            ));
+        }
+        self.controller_mapping = mapping;
+        Ok(())
+    }
+
+    fn map_button_to_chip8_key(&self, button: Button) -> Option<usize> {
+        self.controller_mapping.get(&button).copied()
+    }
+
+    // Open the first joystick SDL recognises as a game controller, if there is one
+    fn open_controller(&self) -> Option<GameController> {
+        let count = self.controller_subsystem.num_joysticks().ok()?;
+        (0..count)
+            .filter(|&index| self.controller_subsystem.is_game_controller(index))
+            .find_map(|index| self.controller_subsystem.open(index).ok())
+    }
+
+    // Step `cycles_per_frame` up or down, in larger steps at higher speeds so going from 10 to
+    // 500 doesn't take ages. Takes effect on the next frame, the timers stay at 60Hz.
+    fn change_speed(chippy: &mut Chippy, faster: bool) {
+        let current = chippy.cycles_per_frame();
+        let delta = (current / 10).max(1);
+        let cycles_per_frame = if faster {
+            current.saturating_add(delta)
+        } else {
+            current.saturating_sub(delta)
+        }
+        .clamp(MIN_CYCLES_PER_FRAME, MAX_CYCLES_PER_FRAME);
+        chippy.set_cycles_per_frame(cycles_per_frame);
+        println!("Cycles per frame: {}", cycles_per_frame);
+    }
+
+    // Instructions to run this frame, `turbo_factor` times `cycles` while turbo is held
+    pub fn turbo_cycles(cycles: u32, turbo_factor: u32, turbo: bool) -> u32 {
+        if turbo {
+            cycles.saturating_mul(turbo_factor.max(1))
+        } else {
+            cycles
+        }
+    }
+
+    // How long to sleep after a frame that took `elapsed` to hit `target_fps`
+    pub fn frame_sleep(elapsed: Duration, target_fps: u32) -> Duration {
+        let frame = Duration::from_secs(1) / target_fps.max(1);
+        frame.saturating_sub(elapsed)
+    }
+
+    fn set_title(&mut self, title: &str) -> Result<(), String> {
+        self.canvas
+            .window_mut()
+            .set_title(title)
+            .map_err(|e| e.to_string())
+    }
+
+    // Where F2-F4 save their captures: next to the ROM, named after it and the current time.
+    // A ROM from stdin has no name, so those go to the working directory as `stdin-<time>`.
+    pub fn capture_path(game_path: &str, extension: &str) -> String {
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let stem = if game_path == "-" { "stdin" } else { game_path };
+        format!("{}-{}.{}", stem, seconds, extension)
+    }
+
+    fn modified_time(path: &str) -> Option<SystemTime> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    //  Implemented from https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/window-properties.rs
+    // Hotkeys:
+    // Escape    - quit
+    // P         - pause/resume, the display keeps rendering while paused
+    // Backspace - reset the machine and reload the ROM
+    // + / ]     - run more instructions per frame
+    // - / [     - run fewer instructions per frame
+    // Tab       - hold to run `turbo_factor` times faster
+    // M         - mute/unmute
+    // F2        - save a screenshot next to the ROM
+    // F3        - start/stop recording a GIF next to the ROM
+    // F4        - dump memory and registers next to the ROM
+    // F5        - show/hide the on-screen keypad, which can be clicked
+    // F6        - show/hide a grid between the pixels
+    // B         - pause and undo the last instruction (debug builds, needs --rewind)
+    pub fn run(&mut self, chippy: &mut Chippy, game_path: &str) -> Result<(), String> {
+        let profile = chippy.load_game(game_path)?;
+        // Replays only match the recording when Cxnn produces the same numbers
+        if let Some(recorder) = &self.input_recording {
+            chippy.set_seed(recorder.seed());
+        }
+        if let Some(replay) = &self.input_replay {
+            chippy.set_seed(replay.seed());
+        }
+        if let Some(profile) = profile {
+            for (color, custom) in self.palette.iter_mut().zip(profile.colors) {
+                if let Some([r, g, b]) = custom {
+                    *color = Color::RGB(r, g, b);
+                }
+            }
+        }
+
+        // Persist the SUPER-CHIP RPL flags next to the ROM so they survive between runs. A ROM
+        // from stdin has nowhere to keep them.
+        let rpl_path = (game_path != "-").then(|| format!("{}.rpl", game_path));
+        if let Some(rpl) = rpl_path.as_ref().and_then(|path| fs::read(path).ok()) {
+            let count = rpl.len().min(chippy.rpl.len());
+            chippy.rpl[..count].copy_from_slice(&rpl[..count]);
+        }
+        let saved_rpl = chippy.rpl;
+
+        // The display is streamed into a texture every frame, which SDL scales to the window
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                chippy.width() as u32,
+                chippy.height() as u32,
+            )
+            .map_err(|e| e.to_string())?;
+        let mut pixels = Vec::new();
+
+        // SDL only sends controller events while the controller is open, so keep it alive
+        let _controller = self.open_controller();
+
+        let mut paused = false;
+        let mut modified = Frontend::modified_time(game_path);
+
+        // The title shows the ROM and the measured frame rate, so a slow machine is easy to spot
+        let rom_name = match Path::new(game_path).file_name() {
+            _ if game_path == "-" => "stdin".to_string(),
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => game_path.to_string(),
+        };
+        self.set_title(&format!("Chippy - {}", rom_name))?;
+        let mut frames = 0;
+        let mut fps_start = Instant::now();
+
+        let mut last_frame = Instant::now();
+        'running: loop {
+            let frame_start = Instant::now();
+            let elapsed = frame_start - last_frame;
+            last_frame = frame_start;
+
+            // Collect the events first, the handlers below need `self` mutably
+            let events: Vec<Event> = self.event_pump.poll_iter().collect();
+            for event in events {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        // Exit the application when escape is pressed
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::P),
+                        repeat: false,
+                        ..
+                    } => paused = !paused,
+                    #[cfg(debug_assertions)]
+                    Event::KeyDown {
+                        keycode: Some(Keycode::B),
+                        ..
+                    } => {
+                        paused = true;
+                        if chippy.step_back() {
+                            println!("Stepped back to {:#05X}", chippy.pc);
+                        } else {
+                            println!("Nothing to step back to");
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::M),
+                        repeat: false,
+                        ..
+                    } => self.toggle_mute(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F2),
+                        repeat: false,
+                        ..
+                    } => {
+                        let path = Frontend::capture_path(game_path, "png");
+                        match self.screenshot_png(chippy, &path) {
+                            Ok(()) => println!("Saved screenshot to {}", path),
+                            Err(e) => eprintln!("Failed to save screenshot: {}", e),
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F3),
+                        repeat: false,
+                        ..
+                    } => {
+                        if self.gif_recording.is_some() {
+                            self.stop_gif_recording()?;
+                            println!("Stopped recording");
+                        } else {
+                            let path = Frontend::capture_path(game_path, "gif");
+                            self.start_gif_recording(chippy, &path)?;
+                            println!("Recording to {}", path);
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F4),
+                        repeat: false,
+                        ..
+                    } => {
+                        let path = Frontend::capture_path(game_path, "dump");
+                        match chippy.dump_memory(&path) {
+                            Ok(()) => println!("Dumped memory to {}", path),
+                            Err(e) => eprintln!("Failed to dump memory: {}", e),
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F5),
+                        repeat: false,
+                        ..
+                    } => {
+                        self.show_keypad = !self.show_keypad;
+                        self.clicked_key = None;
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F6),
+                        repeat: false,
+                        ..
+                    } => self.show_grid = !self.show_grid,
+                    Event::MouseButtonDown {
+                        mouse_btn: MouseButton::Left,
+                        x,
+                        y,
+                        ..
+                    } if self.show_keypad => {
+                        let (window_width, window_height) = self.canvas.output_size()?;
+                        self.clicked_key =
+                            Frontend::keypad_key_at(window_width, window_height, x, y);
+                    }
+                    Event::MouseButtonUp {
+                        mouse_btn: MouseButton::Left,
+                        ..
+                    } => self.clicked_key = None,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Backspace),
+                        repeat: false,
+                        ..
+                    } => {
+                        chippy.reset();
+                        // stdin can only be read once, a piped ROM stays as it left memory
+                        if game_path != "-" {
+                            chippy.load_game(game_path)?;
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode:
+                            Some(
+                                Keycode::Equals
+                                | Keycode::Plus
+                                | Keycode::KpPlus
+                                | Keycode::RightBracket,
+                            ),
+                        ..
+                    } => Frontend::change_speed(chippy, true),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Minus | Keycode::KpMinus | Keycode::LeftBracket),
+                        ..
+                    } => Frontend::change_speed(chippy, false),
+                    // Keys are tracked by what is physically held, so two inputs bound to the
+                    // same CHIP-8 key don't release it while the other is still down. OS key
+                    // repeat only re-inserts a key that is already held, so it never reaches
+                    // Fx0A as a new press.
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        self.held_keys.insert(keycode);
+                    }
+                    Event::KeyUp {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        self.held_keys.remove(&keycode);
+                    }
+                    Event::ControllerButtonDown { button, .. } => {
+                        self.held_buttons.insert(button);
+                    }
+                    Event::ControllerButtonUp { button, .. } => {
+                        self.held_buttons.remove(&button);
+                    }
+                    // Key releases while another window has focus never arrive, so let go of
+                    // everything rather than leave keys stuck down after alt-tabbing
+                    Event::Window {
+                        win_event: WindowEvent::FocusLost,
+                        ..
+                    } => {
+                        self.held_keys.clear();
+                        self.held_buttons.clear();
+                        self.clicked_key = None;
+                        chippy.release_all_keys();
+                    }
+                    _ => {}
+                }
+            }
+            self.update_keypad(chippy);
+
+            // Reload the ROM when it changes on disk, settings like the quirks and CPU speed
+            // live outside the machine state and are kept. The profile isn't applied again, it
+            // would undo speed changes made while running.
+            if self.watch {
+                let current = Frontend::modified_time(game_path);
+                if current != modified {
+                    modified = current;
+                    chippy.reset();
+                    match read_game(game_path).and_then(|rom| chippy.load_bytes(&rom)) {
+                        Ok(()) => println!("Reloaded {}", game_path),
+                        // The file may be half-written, try again on the next change
+                        Err(e) => eprintln!("Failed to reload {}: {}", game_path, e),
+                    }
+                }
+            }
+
+            self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+            self.canvas.clear();
+            if paused {
+                self.pause_audio();
+            } else {
+                // Tab isn't bound to the keypad, it's only tracked in `held_keys` for this
+                let turbo = self.held_keys.contains(&Keycode::Tab);
+                let mut cycles = Frontend::turbo_cycles(
+                    chippy.cycles_for_elapsed(elapsed),
+                    self.turbo_factor,
+                    turbo,
+                );
+                // A replay decides the keys and the instruction count, which can otherwise
+                // depend on timing
+                if let Some(replay) = &mut self.input_replay {
+                    match replay.next_frame() {
+                        Some((keypad, recorded_cycles)) => {
+                            for (key, &held) in keypad.iter().enumerate() {
+                                chippy.set_key(key, held);
+                            }
+                            cycles = recorded_cycles;
+                        }
+                        None => {
+                            println!("Replay finished");
+                            self.input_replay = None;
+                        }
+                    }
+                }
+                if let Some(recorder) = &mut self.input_recording {
+                    recorder.record_frame(&chippy.keypad_state(), cycles)?;
+                }
+                match chippy.run_cycles(cycles)? {
+                    StepResult::Halt if chippy.exited() => {
+                        println!("Program exited");
+                        break 'running;
+                    }
+                    StepResult::Halt => {
+                        println!("Program halted at {:#05X}", chippy.pc);
+                        paused = true;
+                    }
+                    StepResult::Breakpoint(address) => {
+                        println!("Breakpoint at {:#05X}, press P to resume", address);
+                        paused = true;
+                    }
+                    _ => {}
+                }
+                self.play_sound(chippy);
+            }
+            self.drain_recording()?;
+
+            // SUPER-CHIP programs can switch resolution at any time, so resize the texture to match
+            let query = texture.query();
+            if query.width as usize != chippy.width() || query.height as usize != chippy.height() {
+                texture = texture_creator
+                    .create_texture_streaming(
+                        PixelFormatEnum::RGB24,
+                        chippy.width() as u32,
+                        chippy.height() as u32,
+                    )
+                    .map_err(|e| e.to_string())?;
+            }
+            self.update_display(chippy, &mut texture, &mut pixels)?;
+            if self.show_keypad {
+                self.draw_keypad(chippy)?;
+            }
+            self.canvas.present();
+            self.record_gif_frame(chippy)?;
+
+            // Renaming the window goes through the window manager, so only do it once a second
+            frames += 1;
+            let fps_elapsed = fps_start.elapsed();
+            if fps_elapsed >= Duration::from_secs(1) {
+                let fps = frames as f64 / fps_elapsed.as_secs_f64();
+                self.set_title(&format!("Chippy - {} - {:.0} FPS", rom_name, fps))?;
+                frames = 0;
+                fps_start = Instant::now();
+            }
+
+            // vsync alone would run the game at the monitor's refresh rate, e.g. 144Hz
+            thread::sleep(Frontend::frame_sleep(
+                frame_start.elapsed(),
+                self.target_fps,
+            ));
+        }
+
+        self.pause_audio();
+        self.stop_recording()?;
+        self.stop_gif_recording()?;
+        if let Some(recorder) = self.input_recording.take() {
+            recorder.finish()?;
+        }
+        if let Some(rpl_path) = rpl_path.filter(|_| chippy.rpl != saved_rpl) {
+            fs::write(&rpl_path, chippy.rpl).map_err(|e| format!("{}: {}", rpl_path, e))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+// Four colours cover every combination of the two XO-CHIP planes, plain CHIP-8 only uses two
+const PALETTE_BITS: u8 = 2;
+const MAX_CODE: u16 = 4096;
+
+// Map a `display` buffer to palette indices, each pixel's plane bits pick its colour
+pub fn frame_indices(display: &[u8]) -> Vec<u8> {
+    display.iter().map(|&pixel| pixel & 0b11).collect()
+}
+
+// Scale indexed pixels up by repeating each one `scale` times in both directions
+pub fn scale_indices(indices: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    let mut scaled = Vec::with_capacity(indices.len() * scale * scale);
+    for row in indices.chunks_exact(width) {
+        let start = scaled.len();
+        for &index in row {
+            for _ in 0..scale {
+                scaled.push(index);
+            }
+        }
+        for _ in 1..scale {
+            scaled.extend_from_within(start..start + width * scale);
+        }
+    }
+    scaled
+}
+
+// Packs variable width codes least significant bit first, as GIF expects
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+// LZW compression of palette indices with the variable code size used by GIF
+pub fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut writer = BitWriter {
+        bytes: Vec::new(),
+        buffer: 0,
+        bits: 0,
+    };
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut size = min_code_size + 1;
+
+    writer.write(clear, size);
+    let Some((&first, rest)) = indices.split_first() else {
+        writer.write(end, size);
+        return writer.finish();
+    };
+
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = dictionary.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        writer.write(prefix, size);
+        if next_code < MAX_CODE {
+            dictionary.insert((prefix, index), next_code);
+            next_code += 1;
+            if next_code > 1 << size && size < 12 {
+                size += 1;
+            }
+        } else {
+            // The table is full, start over rather than keep using the stale codes
+            writer.write(clear, size);
+            dictionary.clear();
+            next_code = end + 1;
+            size = min_code_size + 1;
+        }
+        prefix = index as u16;
+    }
+    writer.write(prefix, size);
+    writer.write(end, size);
+    writer.finish()
+}
+
+// Streams an animated GIF to disk a frame at a time, so recordings don't grow in memory
+pub struct GifWriter<W: Write = BufWriter<File>> {
+    file: W,
+    width: u16,
+    height: u16,
+}
+
+impl GifWriter {
+    pub fn create(
+        path: &str,
+        width: u16,
+        height: u16,
+        palette: &[[u8; 3]; 4],
+    ) -> Result<GifWriter, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        GifWriter::new(BufWriter::new(file), width, height, palette)
+    }
+}
+
+impl<W: Write> GifWriter<W> {
+    // Write to any output, e.g. a `Vec<u8>` to keep the GIF in memory
+    pub fn new(
+        file: W,
+        width: u16,
+        height: u16,
+        palette: &[[u8; 3]; 4],
+    ) -> Result<GifWriter<W>, String> {
+        let mut gif = GifWriter {
+            file,
+            width,
+            height,
+        };
+
+        let mut header = b"GIF89a".to_vec();
+        header.extend_from_slice(&width.to_le_bytes());
+        header.extend_from_slice(&height.to_le_bytes());
+        // Global colour table with 2^PALETTE_BITS entries, no background or aspect ratio
+        header.extend_from_slice(&[0x80 | (PALETTE_BITS - 1), 0, 0]);
+        for color in palette {
+            header.extend_from_slice(color);
+        }
+        // NETSCAPE2.0 extension so the animation loops forever
+        header.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        header.extend_from_slice(b"NETSCAPE2.0");
+        header.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+        gif.write(&header)?;
+        Ok(gif)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.file.write_all(bytes).map_err(|e| e.to_string())
+    }
+
+    // Add a frame of `width * height` palette indices shown for `delay` hundredths of a second
+    pub fn write_frame(&mut self, indices: &[u8], delay: u16) -> Result<(), String> {
+        let mut frame = vec![0x21, 0xF9, 0x04, 0x00];
+        frame.extend_from_slice(&delay.to_le_bytes());
+        frame.extend_from_slice(&[0x00, 0x00]);
+
+        // Image descriptor covering the whole canvas, using the global colour table
+        frame.push(0x2C);
+        frame.extend_from_slice(&[0, 0, 0, 0]);
+        frame.extend_from_slice(&self.width.to_le_bytes());
+        frame.extend_from_slice(&self.height.to_le_bytes());
+        frame.push(0x00);
+
+        frame.push(PALETTE_BITS);
+        for block in lzw_encode(indices, PALETTE_BITS).chunks(255) {
+            frame.push(block.len() as u8);
+            frame.extend_from_slice(block);
+        }
+        frame.push(0x00);
+        self.write(&frame)
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.write(&[0x3B])?;
+        self.file.flush().map_err(|e| e.to_string())
+    }
+}
+
+// Shortest frame delay most viewers honour, anything faster is often slowed down to 10cs
+const MIN_FRAME_TIME: f32 = 0.02;
+
+// Turns the frames rendered at the emulator's frame rate into GIF frames. Runs of identical
+// frames become one longer frame, and frames shorter than MIN_FRAME_TIME are dropped in favour
+// of the next one, so XOR flicker doesn't trip up the viewer's timing.
+pub struct GifRecorder {
+    writer: GifWriter,
+    pending: Option<Vec<u8>>,
+    pending_time: f32,
+    // Rounding error carried between delays, in hundredths of a second
+    carry: f32,
+}
+
+impl GifRecorder {
+    pub fn new(writer: GifWriter) -> GifRecorder {
+        GifRecorder {
+            writer,
+            pending: None,
+            pending_time: 0.0,
+            carry: 0.0,
+        }
+    }
+
+    // Add a frame that was on screen for `duration` seconds
+    pub fn add_frame(&mut self, indices: Vec<u8>, duration: f32) -> Result<(), String> {
+        if self.pending.as_ref() == Some(&indices) {
+            self.pending_time += duration;
+            return Ok(());
+        }
+        if self.pending_time >= MIN_FRAME_TIME {
+            self.flush()?;
+            self.pending_time = 0.0;
+        }
+        self.pending = Some(indices);
+        self.pending_time += duration;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let Some(pending) = &self.pending else {
+            return Ok(());
+        };
+        let centiseconds = self.pending_time * 100.0 + self.carry;
+        let delay = centiseconds.round();
+        self.carry = centiseconds - delay;
+        self.writer
+            .write_frame(pending, delay.min(u16::MAX as f32) as u16)
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.flush()?;
+        self.writer.finish()
+    }
+}
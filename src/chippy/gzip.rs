@@ -0,0 +1,304 @@
+use super::png::{crc32, deflate_stored};
+
+// Minimal gzip support for compressed ROMs. `decompress` inflates all three deflate block types,
+// `compress` only writes stored blocks, like the PNG encoder.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const METHOD_DEFLATE: u8 = 8;
+
+// Header flags
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+// ROMs never get anywhere near this, it stops a corrupt or malicious file from filling memory
+const MAX_OUTPUT: usize = 1 << 20;
+
+// Base values and extra bits of the length codes 257-285 and distance codes 0-29
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Order the code length code lengths of a dynamic block are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+// Wrap `data` in a gzip file without compressing it
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = GZIP_MAGIC.to_vec();
+    // Deflate, no flags, no modification time, no extra flags, unknown OS
+    out.extend_from_slice(&[METHOD_DEFLATE, 0, 0, 0, 0, 0, 0, 0xFF]);
+    out.extend_from_slice(&deflate_stored(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+// Unpack a gzip file, checking its CRC and length. Only the first member is read, which is all
+// gzip itself writes for a single file.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 18 || !is_gzip(data) {
+        return Err("not a gzip file".to_string());
+    }
+    if data[2] != METHOD_DEFLATE {
+        return Err(format!("unsupported gzip compression method {}", data[2]));
+    }
+    let flags = data[3];
+    let mut position = 10;
+    let truncated = || "truncated gzip header".to_string();
+    if flags & FEXTRA != 0 {
+        let length = data.get(position..position + 2).ok_or_else(truncated)?;
+        position += 2 + u16::from_le_bytes([length[0], length[1]]) as usize;
+    }
+    // The original file name and a comment, both zero terminated
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data
+                .get(position..)
+                .and_then(|rest| rest.iter().position(|&byte| byte == 0))
+                .ok_or_else(truncated)?;
+            position += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        position += 2;
+    }
+    if position + 8 > data.len() {
+        return Err(truncated());
+    }
+
+    let mut reader = BitReader::new(&data[position..data.len() - 8]);
+    let out = inflate(&mut reader)?;
+    let trailer = &data[data.len() - 8..];
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    if crc32(&out) != crc || out.len() as u32 != size {
+        return Err("gzip checksum mismatch, the file is corrupt".to_string());
+    }
+    Ok(out)
+}
+
+// Reads deflate's bit stream, least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn new(data: &[u8]) -> BitReader<'_> {
+        BitReader { data, position: 0 }
+    }
+
+    fn bit(&mut self) -> Result<u16, String> {
+        let byte = self
+            .data
+            .get(self.position / 8)
+            .ok_or("unexpected end of compressed data")?;
+        let bit = (byte >> (self.position % 8)) & 1;
+        self.position += 1;
+        Ok(bit as u16)
+    }
+
+    fn bits(&mut self, count: u8) -> Result<u16, String> {
+        let mut value = 0;
+        for shift in 0..count {
+            value |= self.bit()? << shift;
+        }
+        Ok(value)
+    }
+
+    // Stored blocks start on a byte boundary
+    fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+}
+
+// Canonical Huffman code, decoded a bit at a time: how many codes there are of each length and
+// the symbols sorted by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for length in 1..16 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, &l)| l == length) {
+                symbols.push(symbol as u16);
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        // Codes of each length follow on from the last code of the previous length
+        let (mut code, mut first, mut index) = (0u32, 0u32, 0u32);
+        for &count in &self.counts[1..] {
+            let count = count as u32;
+            code |= reader.bit()? as u32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn inflate(reader: &mut BitReader) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bit()? == 1;
+        match reader.bits(2)? {
+            0 => stored_block(reader, &mut out)?,
+            1 => {
+                let (literals, distances) = fixed_codes();
+                compressed_block(reader, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(reader)?;
+                compressed_block(reader, &mut out, &literals, &distances)?;
+            }
+            _ => return Err("invalid deflate block type".to_string()),
+        }
+        if out.len() > MAX_OUTPUT {
+            return Err(format!("decompresses to more than {} bytes", MAX_OUTPUT));
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn stored_block(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<(), String> {
+    reader.align();
+    let start = reader.position / 8;
+    let header = reader
+        .data
+        .get(start..start + 4)
+        .ok_or("unexpected end of compressed data")?;
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    if length != !u16::from_le_bytes([header[2], header[3]]) {
+        return Err("corrupt stored block length".to_string());
+    }
+    let block = reader
+        .data
+        .get(start + 4..start + 4 + length as usize)
+        .ok_or("unexpected end of compressed data")?;
+    out.extend_from_slice(block);
+    reader.position = (start + 4 + length as usize) * 8;
+    Ok(())
+}
+
+// The codes of block type 1, fixed by the deflate specification
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+// Block type 2 starts with its own codes, themselves Huffman coded
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_length_lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_lengths.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or("repeat with no previous code length")?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("code lengths overrun the table".to_string());
+    }
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn compressed_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)?;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let code = symbol as usize - 257;
+                let (Some(&base), Some(&extra)) = (LENGTH_BASE.get(code), LENGTH_EXTRA.get(code))
+                else {
+                    return Err(format!("invalid length code {}", symbol));
+                };
+                let length = (base + reader.bits(extra)?) as usize;
+
+                let code = distances.decode(reader)? as usize;
+                let (Some(&base), Some(&extra)) =
+                    (DISTANCE_BASE.get(code), DISTANCE_EXTRA.get(code))
+                else {
+                    return Err(format!("invalid distance code {}", code));
+                };
+                let distance = (base + reader.bits(extra)?) as usize;
+                if distance > out.len() {
+                    return Err("distance reaches back before the start of the data".to_string());
+                }
+                // The copy can overlap what it's writing, e.g. distance 1 repeats the last byte
+                let start = out.len() - distance;
+                for index in start..start + length {
+                    out.push(out[index]);
+                }
+                if out.len() > MAX_OUTPUT {
+                    return Err(format!("decompresses to more than {} bytes", MAX_OUTPUT));
+                }
+            }
+        }
+    }
+}
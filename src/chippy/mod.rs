@@ -1,2 +1,18 @@
+pub mod assembler;
+#[cfg(feature = "sdl")]
 pub mod audio;
+pub mod buzzer;
+#[allow(clippy::module_inception)]
 pub mod chippy;
+pub mod disassembler;
+#[cfg(feature = "sdl")]
+pub mod frontend;
+pub mod gif;
+pub mod gzip;
+pub mod png;
+pub mod profile;
+pub mod quirks;
+pub mod random;
+pub mod replay;
+pub mod terminal;
+pub mod wav;
@@ -0,0 +1,100 @@
+#[cfg(feature = "sdl")]
+use std::fs;
+
+use super::chippy::Chippy;
+use super::quirks::{Platform, Quirks};
+
+// Per-game settings read from a sidecar file next to the ROM, e.g. `game.ch8.cfg`:
+//
+//   # Comments start with a hash
+//   platform = schip
+//   cycles_per_frame = 30
+//   clip_sprites = false
+//   color1 = 33FF66
+//
+// The platform preset is applied first, individual quirks override it. `color0` to `color3`
+// are the display colours for each combination of the XO-CHIP planes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub platform: Option<Platform>,
+    pub quirks: Vec<(String, bool)>,
+    pub cycles_per_frame: Option<u32>,
+    pub colors: [Option<[u8; 3]>; 4],
+}
+
+impl Profile {
+    pub fn parse(contents: &str) -> Result<Profile, String> {
+        let mut profile = Profile::default();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected key = value, got {}", line)))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            match key {
+                "platform" => profile.platform = Some(value.parse().map_err(error)?),
+                "cycles_per_frame" => {
+                    let cycles = value.parse().map_err(|e| error(format!("{}", e)))?;
+                    profile.cycles_per_frame = Some(cycles);
+                }
+                "color0" | "color1" | "color2" | "color3" => {
+                    let index = (key.as_bytes()[5] - b'0') as usize;
+                    profile.colors[index] = Some(parse_color(value).map_err(error)?);
+                }
+                _ => {
+                    let value = value
+                        .parse()
+                        .map_err(|_| error(format!("expected true or false for {}", key)))?;
+                    // Check the name now so typos are reported when the file is loaded
+                    Quirks::default().set(key, value).map_err(error)?;
+                    profile.quirks.push((key.to_string(), value));
+                }
+            }
+        }
+        Ok(profile)
+    }
+
+    // Read `<rom>.cfg` if it exists. A ROM piped in on stdin (`-`) has no sidecar.
+    #[cfg(feature = "sdl")]
+    pub fn for_rom(rom_path: &str) -> Result<Option<Profile>, String> {
+        if rom_path == "-" {
+            return Ok(None);
+        }
+        let path = format!("{}.cfg", rom_path);
+        match fs::read_to_string(&path) {
+            Ok(contents) => Profile::parse(&contents)
+                .map(Some)
+                .map_err(|e| format!("{}: {}", path, e)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    // Apply the settings that belong to the emulator core, the colours are up to the frontend
+    pub fn apply(&self, chippy: &mut Chippy) {
+        if let Some(platform) = self.platform {
+            chippy.set_platform(platform);
+        }
+        for (name, value) in &self.quirks {
+            // Names were checked by `parse`
+            let _ = chippy.quirks.set(name, *value);
+        }
+        if let Some(cycles_per_frame) = self.cycles_per_frame {
+            chippy.set_cycles_per_frame(cycles_per_frame);
+        }
+    }
+}
+
+// Colours are six hex digits, RRGGBB, with an optional leading #
+fn parse_color(value: &str) -> Result<[u8; 3], String> {
+    let hex = value.trim_start_matches('#');
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid colour {}", value))?;
+    if hex.len() != 6 {
+        return Err(format!("invalid colour {}, expected RRGGBB", value));
+    }
+    Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+}
@@ -0,0 +1,127 @@
+use std::str::FromStr;
+
+// Behaviours that differ between CHIP-8 interpreters, see
+// https://github.com/Timendus/chip8-test-suite#quirks-test
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    // Fx55/Fx65 advance I past the last register (classic) instead of leaving it (SUPER-CHIP)
+    pub memory_increment_i: bool,
+
+    // 8xy6/8xyE shift Vy into Vx (COSMAC VIP) instead of shifting Vx in place (SUPER-CHIP)
+    pub shift_vy_into_vx: bool,
+
+    // Bnnn is read as BXNN and jumps to XNN + Vx (SUPER-CHIP) instead of nnn + V0
+    pub bxnn_uses_vx: bool,
+
+    // Dxyn clips sprites at the screen edges instead of wrapping them around to the other side
+    pub clip_sprites: bool,
+
+    // Fx1E sets VF when I overflows past 0xFFF (Amiga interpreter, needed by Spacefight 2091!)
+    pub i_overflow_sets_vf: bool,
+
+    // Dxyn waits for the vertical blank, so at most one sprite is drawn per frame (COSMAC VIP)
+    pub display_wait: bool,
+
+    // 8xy1/8xy2/8xy3 reset VF to 0 after the operation (COSMAC VIP)
+    pub logic_resets_vf: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            memory_increment_i: true,
+            shift_vy_into_vx: false,
+            bxnn_uses_vx: false,
+            clip_sprites: true,
+            i_overflow_sets_vf: false,
+            display_wait: false,
+            logic_resets_vf: false,
+        }
+    }
+}
+
+// Interpreters that the quirk presets are modelled on, named like the Timendus test suite
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    // The original COSMAC VIP interpreter
+    Chip8,
+    // SUPER-CHIP 1.1 on the HP48
+    SuperChip,
+    // Octo's XO-CHIP
+    XoChip,
+}
+
+impl Quirks {
+    // Preset flags for each platform:
+    //
+    //                      CHIP-8  SUPER-CHIP  XO-CHIP
+    //   memory_increment_i  on      off         on
+    //   shift_vy_into_vx    on      off         on
+    //   bxnn_uses_vx        off     on          off
+    //   clip_sprites        on      on          off
+    //   i_overflow_sets_vf  off     off         off
+    //   display_wait        on      off         off
+    //   logic_resets_vf     on      off         off
+    pub fn for_platform(platform: Platform) -> Quirks {
+        match platform {
+            Platform::Chip8 => Quirks {
+                memory_increment_i: true,
+                shift_vy_into_vx: true,
+                bxnn_uses_vx: false,
+                clip_sprites: true,
+                i_overflow_sets_vf: false,
+                display_wait: true,
+                logic_resets_vf: true,
+            },
+            Platform::SuperChip => Quirks {
+                memory_increment_i: false,
+                shift_vy_into_vx: false,
+                bxnn_uses_vx: true,
+                clip_sprites: true,
+                i_overflow_sets_vf: false,
+                display_wait: false,
+                logic_resets_vf: false,
+            },
+            Platform::XoChip => Quirks {
+                memory_increment_i: true,
+                shift_vy_into_vx: true,
+                bxnn_uses_vx: false,
+                clip_sprites: false,
+                i_overflow_sets_vf: false,
+                display_wait: false,
+                logic_resets_vf: false,
+            },
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Platform, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Ok(Platform::SuperChip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            _ => Err("unknown platform, expected chip8, schip or xochip".to_string()),
+        }
+    }
+}
+
+impl Quirks {
+    // Set a quirk by its field name, as used in `.cfg` profiles
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        let quirk = match name {
+            "memory_increment_i" => &mut self.memory_increment_i,
+            "shift_vy_into_vx" => &mut self.shift_vy_into_vx,
+            "bxnn_uses_vx" => &mut self.bxnn_uses_vx,
+            "clip_sprites" => &mut self.clip_sprites,
+            "i_overflow_sets_vf" => &mut self.i_overflow_sets_vf,
+            "display_wait" => &mut self.display_wait,
+            "logic_resets_vf" => &mut self.logic_resets_vf,
+            _ => return Err(format!("unknown quirk {}", name)),
+        };
+        *quirk = value;
+        Ok(())
+    }
+}
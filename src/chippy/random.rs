@@ -0,0 +1,65 @@
+#[cfg(feature = "rand")]
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// Random bytes for Cxnn. Uses `rand` when the feature is enabled, otherwise a xorshift generator
+// so the core builds without any dependencies. Unseeded generators differ between runs.
+pub struct Random {
+    #[cfg(feature = "rand")]
+    rng: Option<StdRng>,
+    #[cfg(not(feature = "rand"))]
+    state: u64,
+}
+
+impl Random {
+    pub fn new() -> Random {
+        #[cfg(feature = "rand")]
+        {
+            Random { rng: None }
+        }
+        #[cfg(not(feature = "rand"))]
+        {
+            let seed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64);
+            let mut random = Random { state: 0 };
+            random.seed(seed);
+            random
+        }
+    }
+
+    // The same seed always produces the same sequence of bytes
+    pub fn seed(&mut self, seed: u64) {
+        #[cfg(feature = "rand")]
+        {
+            self.rng = Some(StdRng::seed_from_u64(seed));
+        }
+        #[cfg(not(feature = "rand"))]
+        {
+            // xorshift gets stuck on zero, so mix the seed into a nonzero state
+            self.state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        }
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        #[cfg(feature = "rand")]
+        {
+            match &mut self.rng {
+                Some(rng) => rng.gen::<u8>(),
+                None => rand::random::<u8>(),
+            }
+        }
+        #[cfg(not(feature = "rand"))]
+        {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            (self.state >> 56) as u8
+        }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
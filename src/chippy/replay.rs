@@ -0,0 +1,128 @@
+use std::io::Write;
+#[cfg(feature = "sdl")]
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+};
+
+// Input recordings start with this, followed by the seed the random number generator was given
+// and then one entry per frame: the held keys as a 16-bit mask (bit n is key n) and how many
+// instructions the frame ran, both little endian. Replaying the same keys for the same number of
+// instructions from the same seed reproduces a run exactly.
+const REPLAY_MAGIC: &[u8] = b"CHPYINPT";
+const REPLAY_VERSION: u8 = 1;
+const HEADER_SIZE: usize = 8 + 1 + 8;
+const FRAME_SIZE: usize = 2 + 4;
+
+fn keys_to_mask(keypad: &[bool; 16]) -> u16 {
+    keypad
+        .iter()
+        .enumerate()
+        .fold(0, |mask, (key, &held)| mask | (held as u16) << key)
+}
+
+fn mask_to_keys(mask: u16) -> [bool; 16] {
+    std::array::from_fn(|key| mask & 1 << key != 0)
+}
+
+// Writes the keypad of every frame to a file, or anything else writable, as it's played
+pub struct InputRecorder<W: Write> {
+    writer: W,
+    seed: u64,
+}
+
+#[cfg(feature = "sdl")]
+impl InputRecorder<BufWriter<File>> {
+    pub fn create(path: &str, seed: u64) -> Result<InputRecorder<BufWriter<File>>, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        InputRecorder::new(BufWriter::new(file), seed)
+    }
+}
+
+impl<W: Write> InputRecorder<W> {
+    // The machine has to be seeded with `seed` before the first frame for the replay to match
+    pub fn new(writer: W, seed: u64) -> Result<InputRecorder<W>, String> {
+        let mut recorder = InputRecorder { writer, seed };
+        let mut header = REPLAY_MAGIC.to_vec();
+        header.push(REPLAY_VERSION);
+        header.extend_from_slice(&seed.to_le_bytes());
+        recorder.write(&header)?;
+        Ok(recorder)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.writer.write_all(bytes).map_err(|e| e.to_string())
+    }
+
+    // Add a frame that ran `cycles` instructions with `keypad` held
+    pub fn record_frame(&mut self, keypad: &[bool; 16], cycles: u32) -> Result<(), String> {
+        let mut frame = keys_to_mask(keypad).to_le_bytes().to_vec();
+        frame.extend_from_slice(&cycles.to_le_bytes());
+        self.write(&frame)
+    }
+
+    // Flush the recording and hand back the writer
+    pub fn finish(mut self) -> Result<W, String> {
+        self.writer.flush().map_err(|e| e.to_string())?;
+        Ok(self.writer)
+    }
+}
+
+// The frames of a recording, handed out one at a time in place of live input
+pub struct InputReplay {
+    seed: u64,
+    frames: Vec<([bool; 16], u32)>,
+    position: usize,
+}
+
+impl InputReplay {
+    #[cfg(feature = "sdl")]
+    pub fn open(path: &str) -> Result<InputReplay, String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        InputReplay::parse(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<InputReplay, String> {
+        if data.len() < HEADER_SIZE || !data.starts_with(REPLAY_MAGIC) {
+            return Err("not an input recording".to_string());
+        }
+        let version = data[REPLAY_MAGIC.len()];
+        if version != REPLAY_VERSION {
+            return Err(format!("unsupported input recording version {}", version));
+        }
+        let seed_bytes = &data[REPLAY_MAGIC.len() + 1..HEADER_SIZE];
+        let seed = u64::from_le_bytes(seed_bytes.try_into().unwrap());
+
+        // A recording cut off mid-frame, e.g. by a crash, still replays every complete frame
+        let frames = data[HEADER_SIZE..]
+            .chunks_exact(FRAME_SIZE)
+            .map(|frame| {
+                let mask = u16::from_le_bytes([frame[0], frame[1]]);
+                let cycles = u32::from_le_bytes([frame[2], frame[3], frame[4], frame[5]]);
+                (mask_to_keys(mask), cycles)
+            })
+            .collect();
+        Ok(InputReplay {
+            seed,
+            frames,
+            position: 0,
+        })
+    }
+
+    // What the machine has to be seeded with before the first frame
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // The keys held during the next frame and how many instructions it ran, None once the
+    // recording is over
+    pub fn next_frame(&mut self) -> Option<([bool; 16], u32)> {
+        let frame = self.frames.get(self.position).copied()?;
+        self.position += 1;
+        Some(frame)
+    }
+}
@@ -0,0 +1,34 @@
+use super::chippy::Chippy;
+
+// Text rendering of the display, for running over SSH or in CI logs without SDL
+impl Chippy {
+    // One character per pixel, `█` for lit and a space for unlit, one line per row
+    pub fn render_to_terminal(&self) -> String {
+        let mut output = String::with_capacity((self.width() * 3 + 1) * self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                output.push(if self.pixel(x, y) { '█' } else { ' ' });
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    // Two rows per character using half blocks, terminal cells are about twice as tall as they
+    // are wide so this keeps the display closer to its real aspect ratio
+    pub fn render_to_terminal_half_blocks(&self) -> String {
+        let mut output = String::with_capacity((self.width() * 3 + 1) * self.height() / 2);
+        for y in (0..self.height()).step_by(2) {
+            for x in 0..self.width() {
+                output.push(match (self.pixel(x, y), self.pixel(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            output.push('\n');
+        }
+        output
+    }
+}
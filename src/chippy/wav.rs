@@ -0,0 +1,81 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+};
+
+const HEADER_SIZE: u32 = 44;
+
+// 44 byte header of a mono 16-bit PCM WAV file holding `samples` samples
+pub fn wav_header(sample_rate: u32, samples: u32) -> [u8; 44] {
+    let data_size = samples * 2;
+    let mut header = [0; 44];
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&(HEADER_SIZE - 8 + data_size).to_le_bytes());
+    header[8..12].copy_from_slice(b"WAVE");
+    header[12..16].copy_from_slice(b"fmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    header[20..22].copy_from_slice(&1u16.to_le_bytes()); // PCM
+    header[22..24].copy_from_slice(&1u16.to_le_bytes()); // mono
+    header[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+    header[28..32].copy_from_slice(&(sample_rate * 2).to_le_bytes()); // bytes per second
+    header[32..34].copy_from_slice(&2u16.to_le_bytes()); // bytes per sample
+    header[34..36].copy_from_slice(&16u16.to_le_bytes()); // bits per sample
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+// Streams samples to a WAV file, the sizes in the header are only known once `finish` is called
+pub struct WavWriter<W: Write + Seek = BufWriter<File>> {
+    file: W,
+    sample_rate: u32,
+    samples: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &str, sample_rate: u32) -> Result<WavWriter, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        WavWriter::new(BufWriter::new(file), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    // Write to any seekable output, e.g. a `Cursor` to keep the WAV in memory
+    pub fn new(mut file: W, sample_rate: u32) -> Result<WavWriter<W>, String> {
+        file.write_all(&wav_header(sample_rate, 0))
+            .map_err(|e| e.to_string())?;
+        Ok(WavWriter {
+            file,
+            sample_rate,
+            samples: 0,
+        })
+    }
+
+    // Samples range from -1.0 to 1.0 and are converted to 16-bit integers
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file
+                .write_all(&value.to_le_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    // Rewrite the header with the final sizes and flush everything to disk
+    pub fn finish(mut self) -> Result<(), String> {
+        self.file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| {
+                self.file
+                    .write_all(&wav_header(self.sample_rate, self.samples))
+            })
+            .and_then(|_| self.file.flush())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -1,9 +1,204 @@
-mod chippy;
 extern crate sdl2;
 
-use chippy::chippy::Chippy;
+use std::{env, str::FromStr};
+
+use chippy::chippy::{
+    chippy::Chippy,
+    disassembler::mnemonic,
+    frontend::{Frontend, Vsync},
+};
+
+const USAGE: &str = "Usage: chippy [options] <rom>
+
+A ROM of - is read from stdin, e.g. cat game.ch8 | chippy -
+Gzipped ROMs (game.ch8.gz) are unpacked automatically
+
+Options:
+  --cycles-per-frame N  Instructions executed per 60Hz frame (default 10)
+  --clock-hz N          Instructions per second of real time, instead of per frame
+  --platform NAME       Quirk preset: chip8, schip or xochip
+  --memory-size BYTES   Memory size, 65536 for XO-CHIP (default 4096)
+  --load-address ADDR   Where the ROM is loaded and starts (hex, default 200)
+  --seed N              Seed the random number generator for reproducible runs
+  --trace               Print every executed instruction
+  --strict              Stop on unknown opcodes and stack errors
+  --watch               Reload the ROM when it changes on disk
+  --fps N               Frames per second, the timers tick once per frame (default 60)
+  --min-beep FRAMES     Shortest beep in frames, so very short sounds are audible (default 2)
+  --beep-threshold N    Ignore sound timer values below N (default 1)
+  --turbo N             How many times faster the game runs while Tab is held (default 4)
+  --vsync MODE          auto, on or off, auto falls back to no vsync if it's unsupported
+  --ghosting            Fade pixels out over a few frames to reduce flicker
+  --record-audio PATH   Write everything the buzzer plays to a WAV file
+  --record-input PATH   Record the keypad every frame so the run can be replayed exactly
+  --play-input PATH     Replay a recording made with --record-input instead of live input
+  --headless CYCLES     Run without a window and print the display to the terminal,
+                        stopping early if the program jumps to itself
+  --rewind N            Keep the last N instructions so B can step back (debug builds)
+  --break ADDRESS       Pause before executing the instruction at ADDRESS (hex), repeatable
+  --cheat ADDRESS=VALUE Keep the byte at ADDRESS set to VALUE (both hex), repeatable";
+
+// Parse the value following a flag like `--cycles-per-frame 15`
+fn parse_value<T>(flag: &str, value: Option<String>) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = value.ok_or_else(|| format!("{} needs a value\n{}", flag, USAGE))?;
+    value
+        .parse::<T>()
+        .map_err(|e| format!("Invalid {} {}: {}", flag, value, e))
+}
+
+// Parse a cheat like `--cheat 2A4=09` into an address and the value kept there
+fn parse_cheat(flag: &str, value: Option<String>) -> Result<(u16, u8), String> {
+    let value: String = parse_value(flag, value)?;
+    let invalid = |e: &dyn std::fmt::Display| format!("Invalid {} {}: {}", flag, value, e);
+    let (address, byte) = value
+        .split_once('=')
+        .ok_or_else(|| invalid(&"expected ADDRESS=VALUE"))?;
+    let address =
+        u16::from_str_radix(address.trim_start_matches("0x"), 16).map_err(|e| invalid(&e))?;
+    let byte = u8::from_str_radix(byte.trim_start_matches("0x"), 16).map_err(|e| invalid(&e))?;
+    Ok((address, byte))
+}
+
+// Parse a hexadecimal address like `--break 0x2A4` or `--load-address 600`
+fn parse_address(flag: &str, value: Option<String>) -> Result<u16, String> {
+    let value: String = parse_value(flag, value)?;
+    u16::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("Invalid {} {}: {}", flag, value, e))
+}
 
 fn main() -> Result<(), String> {
+    let mut rom_path = None;
+    let mut cycles_per_frame = None;
+    let mut clock_hz = None;
+    let mut seed = None;
+    let mut platform = None;
+    let mut memory_size = None;
+    let mut load_address = None;
+    let mut headless_cycles = None;
+    let mut fps = None;
+    let mut turbo = None;
+    let mut min_beep = None;
+    let mut beep_threshold = None;
+    let mut vsync = Vsync::Auto;
+    let mut record_audio = None;
+    let mut record_input = None;
+    let mut play_input = None;
+    let mut rewind = None;
+    let mut breakpoints = Vec::new();
+    let mut cheats = Vec::new();
+    let mut trace = false;
+    let mut strict = false;
+    let mut watch = false;
+    let mut ghosting = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cycles-per-frame" => cycles_per_frame = Some(parse_value(&arg, args.next())?),
+            "--platform" => platform = Some(parse_value(&arg, args.next())?),
+            "--memory-size" => memory_size = Some(parse_value(&arg, args.next())?),
+            "--clock-hz" => clock_hz = Some(parse_value(&arg, args.next())?),
+            "--seed" => seed = Some(parse_value(&arg, args.next())?),
+            "--fps" => fps = Some(parse_value(&arg, args.next())?),
+            "--min-beep" => min_beep = Some(parse_value(&arg, args.next())?),
+            "--beep-threshold" => beep_threshold = Some(parse_value(&arg, args.next())?),
+            "--turbo" => turbo = Some(parse_value(&arg, args.next())?),
+            "--vsync" => vsync = parse_value(&arg, args.next())?,
+            "--record-audio" => record_audio = Some(parse_value::<String>(&arg, args.next())?),
+            "--record-input" => record_input = Some(parse_value::<String>(&arg, args.next())?),
+            "--play-input" => play_input = Some(parse_value::<String>(&arg, args.next())?),
+            "--rewind" => rewind = Some(parse_value(&arg, args.next())?),
+            "--headless" => headless_cycles = Some(parse_value(&arg, args.next())?),
+            "--break" => breakpoints.push(parse_address(&arg, args.next())?),
+            "--cheat" => cheats.push(parse_cheat(&arg, args.next())?),
+            "--load-address" => load_address = Some(parse_address(&arg, args.next())?),
+            "--trace" => trace = true,
+            "--strict" => strict = true,
+            "--watch" => watch = true,
+            "--ghosting" => ghosting = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(format!("Unexpected argument {}\n{}", arg, USAGE)),
+        }
+    }
+
+    let Some(rom_path) = rom_path else {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    };
+
     let mut chippy_i: Chippy = Chippy::new();
-    chippy_i.run("/home/rithulk/dev/chippy/test/random_number_test.ch8")
+    if let Some(cycles_per_frame) = cycles_per_frame {
+        chippy_i.set_cycles_per_frame(cycles_per_frame);
+    }
+    if let Some(clock_hz) = clock_hz {
+        chippy_i.set_clock_hz(clock_hz);
+    }
+    if let Some(platform) = platform {
+        chippy_i.set_platform(platform);
+    }
+    if let Some(memory_size) = memory_size {
+        chippy_i.set_memory_size(memory_size)?;
+    }
+    if let Some(load_address) = load_address {
+        chippy_i.load_address = load_address;
+    }
+    if let Some(seed) = seed {
+        chippy_i.set_seed(seed);
+    }
+    for address in breakpoints {
+        chippy_i.add_breakpoint(address);
+    }
+    for (address, value) in cheats {
+        chippy_i.add_cheat(address, value)?;
+    }
+    if let Some(rewind) = rewind {
+        chippy_i.set_rewind_capacity(rewind);
+    }
+    if let Some(min_beep) = min_beep {
+        chippy_i.min_beep_frames = min_beep;
+    }
+    if let Some(beep_threshold) = beep_threshold {
+        chippy_i.beep_threshold = beep_threshold;
+    }
+    chippy_i.strict = strict;
+    if trace {
+        chippy_i.set_instruction_hook(Some(Box::new(|pc, opcode| {
+            println!("{:#05X}: {:04X}  {}", pc, opcode, mnemonic(opcode));
+        })));
+    }
+    if let Some(cycles) = headless_cycles {
+        chippy_i.halt_on_self_jump = true;
+        chippy_i.load_game(&rom_path)?;
+        chippy_i.run_headless(cycles)?;
+        print!("{}", chippy_i.render_to_terminal_half_blocks());
+        return Ok(());
+    }
+
+    let mut frontend = Frontend::with_vsync(vsync)?;
+    frontend.watch = watch;
+    frontend.ghosting = ghosting;
+    if let Some(fps) = fps {
+        frontend.target_fps = fps;
+    }
+    if let Some(turbo) = turbo {
+        frontend.turbo_factor = turbo;
+    }
+    if let Some(path) = record_audio {
+        frontend.start_recording(&path)?;
+    }
+    if let Some(path) = record_input {
+        frontend.start_record(&path)?;
+    }
+    if let Some(path) = play_input {
+        frontend.play(&path)?;
+    }
+    frontend.run(&mut chippy_i, &rom_path)
 }
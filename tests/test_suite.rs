@@ -0,0 +1,2573 @@
+// Runs the test ROMs headless and compares a hash of the final display against the values in
+// `tests/display_hashes.txt`, so any change to what a ROM draws shows up as a failure.
+// After an intentional behaviour change, regenerate the file with:
+//   CHIPPY_BLESS=1 cargo test --test test_suite
+use std::{cell::RefCell, collections::BTreeMap, env, fs, io::Cursor, rc::Rc, time::Duration};
+
+use chippy::chippy::{
+    assembler::assemble,
+    buzzer::{self, AudioBackend, NullAudio},
+    chippy::{rom_warnings, Chippy, EmuMetrics, StepResult, FONT},
+    disassembler::mnemonic,
+    gif::{self, GifWriter},
+    gzip,
+    png::{crc32, encode_png},
+    profile::Profile,
+    quirks::{Platform, Quirks},
+    replay::{InputRecorder, InputReplay},
+    wav::WavWriter,
+};
+
+const HASHES_PATH: &str = "tests/display_hashes.txt";
+
+// ROM, and how many instructions to run before the display is checked. The ROMs draw their
+// results once and then loop forever, so these only need to be long enough to get there.
+const ROMS: &[(&str, u64)] = &[("test/test_opcode.ch8", 10_000), ("test/ibm.ch8", 1_000)];
+
+// FNV-1a, stable between Rust versions unlike `DefaultHasher`
+fn display_hash(chippy: &Chippy) -> u64 {
+    let mut hash = 0xCBF2_9CE4_8422_2325u64;
+    let size = [chippy.width() as u8, chippy.height() as u8];
+    for &byte in size.iter().chain(chippy.display_buffer()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash
+}
+
+fn run_rom(path: &str, cycles: u64) -> u64 {
+    let mut chippy = Chippy::new();
+    chippy.set_seed(0);
+    chippy.halt_on_self_jump = true;
+    chippy.load_bytes(&fs::read(path).unwrap()).unwrap();
+    chippy.run_headless(cycles).unwrap();
+    display_hash(&chippy)
+}
+
+fn expected_hashes() -> BTreeMap<String, u64> {
+    let contents = fs::read_to_string(HASHES_PATH).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(rom, hash)| (rom.to_string(), u64::from_str_radix(hash, 16).unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_roms_draw_the_expected_display() {
+    let actual: BTreeMap<String, u64> = ROMS
+        .iter()
+        .map(|&(path, cycles)| (path.to_string(), run_rom(path, cycles)))
+        .collect();
+
+    if env::var_os("CHIPPY_BLESS").is_some() {
+        let contents: String = actual
+            .iter()
+            .map(|(rom, hash)| format!("{} {:016x}\n", rom, hash))
+            .collect();
+        fs::write(HASHES_PATH, contents).unwrap();
+        return;
+    }
+
+    let expected = expected_hashes();
+    for (rom, hash) in &actual {
+        assert_eq!(
+            expected.get(rom),
+            Some(hash),
+            "display of {} changed, rerun with CHIPPY_BLESS=1 if that was intended",
+            rom
+        );
+    }
+}
+
+// Draws an 8-row sprite of solid rows starting 4 rows above the bottom of the screen
+fn draw_tall_sprite_at_bottom(clip_sprites: bool) -> Chippy {
+    let program = [
+        0x60, 0x00, // LD V0, 0
+        0x61, 0x1C, // LD V1, 28
+        0xA2, 0x0A, // LD I, 0x20A
+        0xD0, 0x18, // DRW V0, V1, 8
+        0x12, 0x08, // JP 0x208
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    ];
+    let mut chippy = Chippy::new();
+    chippy.quirks.clip_sprites = clip_sprites;
+    chippy.load_bytes(&program).unwrap();
+    for _ in 0..4 {
+        chippy.step().unwrap();
+    }
+    chippy
+}
+
+fn lit_rows(chippy: &Chippy) -> Vec<usize> {
+    (0..chippy.height())
+        .filter(|&y| chippy.display_buffer()[y * chippy.width()] != 0)
+        .collect()
+}
+
+#[test]
+fn tall_sprites_clip_at_the_bottom_edge() {
+    let chippy = draw_tall_sprite_at_bottom(true);
+    assert_eq!(lit_rows(&chippy), [28, 29, 30, 31]);
+    assert_eq!(chippy.v[0xF], 0);
+}
+
+#[test]
+fn tall_sprites_wrap_to_the_top_without_clipping() {
+    let chippy = draw_tall_sprite_at_bottom(false);
+    assert_eq!(lit_rows(&chippy), [0, 1, 2, 3, 28, 29, 30, 31]);
+    assert_eq!(chippy.v[0xF], 0);
+}
+
+// The core never touches an audio device, the sound opcodes only set state for a frontend to
+// play, so a machine without one has to run them all the same
+#[test]
+fn sound_opcodes_run_without_audio() {
+    let program = [
+        0x60, 0xFF, // V0 = FF
+        0xF0, 0x18, // sound timer = V0
+        0xF0, 0x02, // load the audio pattern at I
+        0xF0, 0x3A, // pitch = V0
+        0x12, 0x08, // jump to self
+    ];
+    let mut chippy = Chippy::new();
+    chippy.halt_on_self_jump = true;
+    chippy.load_bytes(&program).unwrap();
+    chippy.run_headless(100).unwrap();
+    assert!(chippy.sound_timer > 0);
+    assert!(chippy.audio_pattern().is_some());
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn turbo_multiplies_the_cycles_only_while_held() {
+    use chippy::chippy::frontend::Frontend;
+
+    assert_eq!(Frontend::turbo_cycles(10, 4, false), 10);
+    assert_eq!(Frontend::turbo_cycles(10, 4, true), 40);
+    // A factor of 0 would stop the CPU, it's treated as no speed-up instead
+    assert_eq!(Frontend::turbo_cycles(10, 0, true), 10);
+    assert_eq!(Frontend::turbo_cycles(u32::MAX, 4, true), u32::MAX);
+}
+
+// A square wave the way the frontend opens it, without an audio device
+#[cfg(feature = "sdl")]
+fn square_wave(tone: f32, sample_rate: f32, volume: f32) -> chippy::chippy::audio::Square {
+    use chippy::chippy::audio::{SharedF32, Square};
+
+    Square {
+        phase_inc: tone / sample_rate,
+        phase: 0.0,
+        tone: SharedF32::new(tone),
+        sample_rate,
+        volume: SharedF32::new(volume),
+        pattern: None,
+        pattern_rate: 4000.0,
+        pattern_position: 0.0,
+        recording: None,
+    }
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn buzzer_amplitude_follows_the_volume_and_mute() {
+    use chippy::chippy::frontend::Frontend;
+    use sdl2::audio::AudioCallback;
+
+    let peak = |volume: f32, muted: bool| {
+        let mut square = square_wave(440.0, 44100.0, Frontend::output_volume(volume, muted));
+        let mut out = [0.0; 1024];
+        square.callback(&mut out);
+        out.iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+    };
+
+    // The square wave peaks at half of full scale
+    assert_eq!(peak(1.0, false), 0.5);
+    assert_eq!(peak(0.5, false), 0.25);
+    assert_eq!(peak(0.1, false), 0.05);
+    assert_eq!(peak(1.0, true), 0.0);
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn square_wave_is_bounded_and_smoothed_at_its_edges() {
+    use chippy::chippy::audio::square_sample;
+
+    let phase_inc = 0.01;
+    for step in 0..1000 {
+        let sample = square_sample(step as f32 / 1000.0, phase_inc);
+        assert!((-1.0..=1.0).contains(&sample), "{} out of range", sample);
+    }
+
+    // Away from the edges it's flat at half of full scale
+    assert_eq!(square_sample(0.25, phase_inc), 0.5);
+    assert_eq!(square_sample(0.75, phase_inc), -0.5);
+
+    // Both edges pass through zero instead of jumping straight between the two levels
+    assert_eq!(square_sample(0.0, phase_inc), 0.0);
+    assert_eq!(square_sample(0.5, phase_inc), 0.0);
+    let rising = square_sample(phase_inc / 2.0, phase_inc);
+    let falling = square_sample(0.5 + phase_inc / 2.0, phase_inc);
+    assert!(0.0 < rising && rising < 0.5, "{}", rising);
+    assert!(-0.5 < falling && falling < 0.0, "{}", falling);
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn square_wave_period_follows_the_tone() {
+    use sdl2::audio::AudioCallback;
+
+    // Samples between consecutive rising edges
+    let periods = |out: &[f32]| -> Vec<usize> {
+        let edges: Vec<usize> = (1..out.len())
+            .filter(|&i| out[i - 1] < 0.0 && out[i] >= 0.0)
+            .collect();
+        edges.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    };
+
+    // Tones that divide the sample rate evenly, so the phase adds up exactly
+    let mut square = square_wave(1500.0, 48000.0, 1.0);
+    let mut out = [0.0; 480];
+    square.callback(&mut out);
+    let measured = periods(&out);
+    assert!(measured.len() >= 12);
+    assert!(
+        measured.iter().all(|&period| period == 32),
+        "{:?}",
+        measured
+    );
+
+    // A new tone is picked up on the next block
+    square.tone.set(3000.0);
+    square.callback(&mut out);
+    let measured = periods(&out);
+    assert!(measured.len() >= 25);
+    assert!(
+        measured.iter().all(|&period| period == 16),
+        "{:?}",
+        measured
+    );
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn frame_limiter_sleeps_for_the_rest_of_the_frame() {
+    use chippy::chippy::frontend::Frontend;
+
+    let frame = Duration::from_secs(1) / 60;
+    assert_eq!(Frontend::frame_sleep(Duration::ZERO, 60), frame);
+    assert_eq!(
+        Frontend::frame_sleep(Duration::from_millis(5), 60),
+        frame - Duration::from_millis(5)
+    );
+    assert_eq!(
+        Frontend::frame_sleep(Duration::from_millis(5), 100),
+        Duration::from_millis(5)
+    );
+    // A frame that ran long doesn't sleep at all
+    assert_eq!(
+        Frontend::frame_sleep(Duration::from_millis(20), 60),
+        Duration::ZERO
+    );
+    // An fps of 0 is treated as 1 instead of dividing by zero
+    assert_eq!(
+        Frontend::frame_sleep(Duration::ZERO, 0),
+        Duration::from_secs(1)
+    );
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn ghosting_fades_a_cleared_pixel_to_the_background() {
+    use chippy::chippy::frontend::Frontend;
+
+    // A lit pixel shows at full brightness straight away
+    assert_eq!(Frontend::fade(0.0, 255.0, true), 255.0);
+
+    // Once cleared it dims a little every frame instead of going dark at once
+    let mut brightness = 255.0;
+    let mut frames = Vec::new();
+    for _ in 0..5 {
+        brightness = Frontend::fade(brightness, 0.0, false);
+        frames.push(brightness.round() as u8);
+    }
+    assert_eq!(frames, [153, 92, 55, 33, 20]);
+
+    // and rounds to the background after a few more
+    for _ in 0..8 {
+        brightness = Frontend::fade(brightness, 0.0, false);
+    }
+    assert_eq!(brightness.round() as u8, 0);
+}
+
+#[test]
+fn opcode_stats_count_each_executed_instruction() {
+    let program = [
+        0x60, 0x00, // V0 = 0
+        0x70, 0x01, // V0 += 1
+        0x30, 0x0A, // skip the jump once V0 is 10
+        0x12, 0x02, // jump back to the add
+        0x12, 0x08, // jump to self
+    ];
+    let mut chippy = Chippy::new();
+    chippy.halt_on_self_jump = true;
+    chippy.profile = true;
+    chippy.load_bytes(&program).unwrap();
+    chippy.run_headless(1_000).unwrap();
+
+    let stats = chippy.opcode_stats();
+    assert_eq!(stats.get(&0x6000), Some(&1));
+    assert_eq!(stats.get(&0x7000), Some(&10));
+    assert_eq!(stats.get(&0x3000), Some(&10));
+    // Nine jumps back plus the final jump to self
+    assert_eq!(stats.get(&0x1000), Some(&10));
+    assert_eq!(stats.len(), 4);
+}
+
+#[test]
+fn load_from_reader_copies_the_rom_to_the_load_address() {
+    let rom = vec![0x00, 0xE0, 0x12, 0x02];
+    let mut chippy = Chippy::new();
+    chippy.load_from_reader(Cursor::new(rom.clone())).unwrap();
+    assert_eq!(&chippy.memory[0x200..0x204], &rom[..]);
+    assert_eq!(chippy.pc, 0x200);
+
+    // Readers get the same checks as `load_bytes`
+    assert!(chippy.load_from_reader(Cursor::new(Vec::new())).is_err());
+}
+
+#[test]
+fn rom_warnings_flag_files_that_are_not_chip8() {
+    assert!(rom_warnings(&fs::read("test/ibm.ch8").unwrap()).is_empty());
+    assert_eq!(rom_warnings(&[0x00, 0xE0, 0x12]).len(), 1);
+    assert_eq!(rom_warnings(&[0xFF, 0xD8, 0xFF, 0xE0]).len(), 1);
+
+    let mut chippy = Chippy::new();
+    let error = chippy.load_bytes(&[]).unwrap_err();
+    assert!(error.contains("empty"), "{}", error);
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn load_game_refuses_an_empty_file() {
+    let path = env::temp_dir().join("chippy-empty-test.ch8");
+    fs::write(&path, []).unwrap();
+    let path = path.to_str().unwrap();
+    let error = Chippy::new().load_game(path).unwrap_err();
+    fs::remove_file(path).unwrap();
+    assert_eq!(error, format!("{}: ROM is empty", path));
+}
+
+#[test]
+fn logic_resets_vf_quirk_clears_the_flag() {
+    for opcode in [0x8121u16, 0x8122, 0x8123] {
+        for reset in [false, true] {
+            let mut chippy = Chippy::new();
+            chippy.quirks.logic_resets_vf = reset;
+            // V1 op= V2, with VF holding a leftover flag
+            chippy.load_bytes(&opcode.to_be_bytes()).unwrap();
+            chippy.v[0xF] = 1;
+            chippy.step().unwrap();
+            let expected = if reset { 0 } else { 1 };
+            assert_eq!(chippy.v[0xF], expected, "{:04X}", opcode);
+        }
+    }
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn dump_memory_ends_with_the_memory_image() {
+    let mut chippy = Chippy::new();
+    chippy
+        .load_bytes(&fs::read("test/ibm.ch8").unwrap())
+        .unwrap();
+    chippy.run_headless(100).unwrap();
+
+    let path = env::temp_dir().join("chippy-dump-test.dump");
+    let path = path.to_str().unwrap();
+    chippy.dump_memory(path).unwrap();
+    let dump = fs::read(path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    let header_end = dump.windows(2).position(|bytes| bytes == b"\n\n").unwrap() + 2;
+    let header = String::from_utf8(dump[..header_end].to_vec()).unwrap();
+    assert!(header.starts_with(&format!("PC: {:#05X}", chippy.pc)));
+    assert!(header.contains("Memory: 4096 bytes"));
+    assert_eq!(&dump[header_end..], &chippy.memory[..]);
+}
+
+#[test]
+fn assemble_encodes_each_line() {
+    let source = "
+        CLS
+        LD V0, 0x0A   ; a comment
+        ld v1, 5
+        LD I, 0x22A
+        DRW V0, V1, 5
+        LD [I], V0-V3
+        JP 0x208
+        DB 0xF0, 0b10010000
+    ";
+    assert_eq!(
+        assemble(source).unwrap(),
+        [
+            0x00, 0xE0, 0x60, 0x0A, 0x61, 0x05, 0xA2, 0x2A, 0xD0, 0x15, 0x50, 0x32, 0x12, 0x08,
+            0xF0, 0x90,
+        ]
+    );
+    assert!(assemble("LD V0, 0x100").is_err());
+    assert!(assemble("JP V1, 0x200").is_err());
+}
+
+#[test]
+fn assemble_reverses_the_disassembler() {
+    // Compared by mnemonic, the interpreter ignores x in Fx02 so F102 shows as AUDIO as well
+    for opcode in 0..=u16::MAX {
+        let text = mnemonic(opcode);
+        if text.starts_with("DW") || text == "LD I, LONG" {
+            continue;
+        }
+        let rom = assemble(&text).unwrap();
+        assert_eq!(mnemonic(u16::from_be_bytes([rom[0], rom[1]])), text);
+    }
+}
+
+// Regression test for scrambled font glyphs: the rows have to come out in order with the most
+// significant bit on the left, wherever the glyph is drawn
+#[test]
+fn font_glyphs_draw_exactly_as_stored() {
+    let glyph_a = [0xF0, 0x90, 0xF0, 0x90, 0x90];
+    for x in [0, 3, 60] {
+        let program = assemble(&format!(
+            "
+            LD V0, 0x0A
+            LD F, V0
+            LD V1, {}
+            LD V2, 2
+            DRW V1, V2, 5
+            JP 0x20A
+            ",
+            x
+        ))
+        .unwrap();
+        let mut chippy = Chippy::new();
+        chippy.quirks.clip_sprites = false;
+        chippy.halt_on_self_jump = true;
+        chippy.load_bytes(&program).unwrap();
+        chippy.run_headless(100).unwrap();
+
+        let display = chippy.display_buffer();
+        let rows: Vec<u8> = (0..5)
+            .map(|row| {
+                (0..8).fold(0, |bits, column| {
+                    let pixel_x = (x + column) % chippy.width();
+                    let lit = display[(2 + row) * chippy.width() + pixel_x] != 0;
+                    bits << 1 | lit as u8
+                })
+            })
+            .collect();
+        assert_eq!(rows, glyph_a, "drawn at x = {}", x);
+        assert_eq!(chippy.v[0xF], 0);
+    }
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn vsync_auto_falls_back_to_no_vsync() {
+    use chippy::chippy::frontend::Vsync;
+
+    assert_eq!(Vsync::Auto.attempts(), [true, false]);
+    assert_eq!(Vsync::On.attempts(), [true]);
+    assert_eq!(Vsync::Off.attempts(), [false]);
+    assert_eq!("OFF".parse(), Ok(Vsync::Off));
+    assert!("sometimes".parse::<Vsync>().is_err());
+}
+
+#[test]
+fn keypad_state_reports_exactly_the_pressed_keys() {
+    let mut chippy = Chippy::new();
+    chippy.set_key(0x1, true);
+    chippy.set_key(0xF, true);
+    let mut expected = [false; 16];
+    expected[0x1] = true;
+    expected[0xF] = true;
+    assert_eq!(chippy.keypad_state(), expected);
+
+    chippy.release_all_keys();
+    assert_eq!(chippy.keypad_state(), [false; 16]);
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn keypad_overlay_hitboxes_follow_the_keypad_layout() {
+    use chippy::chippy::frontend::Frontend;
+
+    // 4 keys of 40 pixels with 4 pixel gaps, 10 pixels from the bottom right of 1280x640
+    assert_eq!(Frontend::keypad_key_at(1280, 640, 1098, 458), Some(0x1));
+    assert_eq!(Frontend::keypad_key_at(1280, 640, 1269, 629), Some(0xF));
+    assert_eq!(Frontend::keypad_key_at(1280, 640, 1142, 590), Some(0x0));
+    // Gaps, the margin and the rest of the window are not keys
+    assert_eq!(Frontend::keypad_key_at(1280, 640, 1139, 470), None);
+    assert_eq!(Frontend::keypad_key_at(1280, 640, 1275, 635), None);
+    assert_eq!(Frontend::keypad_key_at(1280, 640, 100, 100), None);
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn each_plane_combination_has_its_own_palette_colour() {
+    use chippy::chippy::frontend::{Frontend, DEFAULT_PALETTE};
+    use sdl2::pixels::Color;
+
+    assert_eq!(
+        Frontend::pixel_color(&DEFAULT_PALETTE, 0b00),
+        Color::RGB(0, 0, 0)
+    );
+    assert_eq!(
+        Frontend::pixel_color(&DEFAULT_PALETTE, 0b01),
+        Color::RGB(255, 255, 255)
+    );
+    let palette = [
+        Color::RGB(1, 1, 1),
+        Color::RGB(2, 2, 2),
+        Color::RGB(3, 3, 3),
+        Color::RGB(4, 4, 4),
+    ];
+    for pixel in 0..4 {
+        assert_eq!(
+            Frontend::pixel_color(&palette, pixel),
+            palette[pixel as usize]
+        );
+    }
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn display_converts_to_rgb24_for_the_texture() {
+    use chippy::chippy::frontend::{Frontend, DEFAULT_PALETTE};
+
+    let mut chippy = Chippy::new();
+    chippy.display[0] = 0b01;
+    chippy.display[1] = 0b10;
+    chippy.display[2] = 0b11;
+    let mut pixels = vec![0xAA; chippy.width() * chippy.height() * 3];
+    Frontend::display_to_rgb24(&DEFAULT_PALETTE, &chippy.display, &mut pixels);
+
+    assert_eq!(pixels[..9], [255, 255, 255, 255, 102, 0, 0, 170, 255]);
+    // Every byte is overwritten, unlit pixels are the background colour
+    assert!(pixels[9..].iter().all(|&channel| channel == 0));
+}
+
+#[test]
+fn delay_timer_only_ticks_between_frames() {
+    let program = assemble(
+        "
+        LD V0, DT   ; the value the frame started with
+        LD V1, 30
+        LD DT, V1
+        LD V2, DT   ; the write is visible straight away
+        LD V3, DT   ; and doesn't tick down within the frame
+        JP 0x20A
+        ",
+    )
+    .unwrap();
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&program).unwrap();
+    chippy.delay_timer = 5;
+    chippy.set_cycles_per_frame(6);
+
+    chippy.run_frame().unwrap();
+    assert_eq!(chippy.v[0], 5);
+    assert_eq!(chippy.v[2], 30);
+    assert_eq!(chippy.v[3], 30);
+    // The one tick of the frame comes after all of its instructions
+    assert_eq!(chippy.delay_timer, 29);
+}
+
+// Tiny xorshift so the fuzz test below is reproducible without the rand feature
+struct Fuzz(u64);
+
+impl Fuzz {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bool(&mut self) -> bool {
+        self.next() & 1 == 1
+    }
+}
+
+// A machine with random registers, memory, settings and resolution
+fn fuzzed_machine(fuzz: &mut Fuzz) -> Chippy {
+    let mut chippy = Chippy::new();
+    if fuzz.next() & 7 == 0 {
+        chippy.set_memory_size(65536).unwrap();
+    }
+    for bytes in chippy.memory.chunks_exact_mut(8) {
+        bytes.copy_from_slice(&fuzz.next().to_le_bytes());
+    }
+    for register in chippy.v.iter_mut().chain(chippy.rpl.iter_mut()) {
+        *register = fuzz.next() as u8;
+    }
+    for address in chippy.stack.iter_mut() {
+        *address = fuzz.next() as u16;
+    }
+    chippy.sp = fuzz.next() as usize % 20;
+    chippy.i = fuzz.next() as u16;
+    chippy.pc = fuzz.next() as u16;
+    chippy.delay_timer = fuzz.next() as u8;
+    chippy.sound_timer = fuzz.next() as u8;
+    chippy.quirks.memory_increment_i = fuzz.bool();
+    chippy.quirks.shift_vy_into_vx = fuzz.bool();
+    chippy.quirks.bxnn_uses_vx = fuzz.bool();
+    chippy.quirks.clip_sprites = fuzz.bool();
+    chippy.quirks.i_overflow_sets_vf = fuzz.bool();
+    chippy.quirks.display_wait = fuzz.bool();
+    chippy.quirks.logic_resets_vf = fuzz.bool();
+    chippy.set_seed(fuzz.next());
+    chippy
+}
+
+// Put `opcode` where the machine's PC points and execute it
+fn step_opcode(chippy: &mut Chippy, opcode: u16) {
+    let mask = chippy.memory.len() - 1;
+    let pc = chippy.pc as usize;
+    let [high, low] = opcode.to_be_bytes();
+    chippy.memory[pc & mask] = high;
+    chippy.memory[(pc + 1) & mask] = low;
+    let _ = chippy.step();
+}
+
+// Every opcode has to either run or return an error, whatever state the machine is in
+#[test]
+fn no_opcode_panics() {
+    let mut fuzz = Fuzz(0x9E37_79B9_7F4A_7C15);
+    for opcode in 0..=u16::MAX {
+        let mut chippy = fuzzed_machine(&mut fuzz);
+        if fuzz.bool() {
+            // Switch to the SUPER-CHIP 128x64 mode first
+            step_opcode(&mut chippy, 0x00FF);
+        }
+        step_opcode(&mut chippy, opcode);
+    }
+}
+
+#[test]
+fn replaying_recorded_input_reproduces_the_display() {
+    // Draws the glyph of every key pressed at a random column
+    let program = assemble(
+        "
+        LD V0, K
+        LD F, V0
+        RND V2, 0x3F
+        DRW V2, V1, 5
+        ADD V1, 6
+        JP 0x200
+        ",
+    )
+    .unwrap();
+    let presses = [(3, 0x5), (9, 0xA), (15, 0x1), (21, 0xF)];
+    let mut recorded = Chippy::new();
+    recorded.load_bytes(&program).unwrap();
+    let mut recorder = InputRecorder::new(Vec::new(), 42).unwrap();
+    recorded.set_seed(recorder.seed());
+    for frame in 0..30 {
+        recorded.release_all_keys();
+        for &(start, key) in &presses {
+            if (start..start + 3).contains(&frame) {
+                recorded.set_key(key, true);
+            }
+        }
+        recorder.record_frame(&recorded.keypad_state(), 10).unwrap();
+        recorded.run_cycles(10).unwrap();
+    }
+    let recording = recorder.finish().unwrap();
+
+    let mut replayed = Chippy::new();
+    replayed.load_bytes(&program).unwrap();
+    let mut replay = InputReplay::parse(&recording).unwrap();
+    replayed.set_seed(replay.seed());
+    while let Some((keypad, cycles)) = replay.next_frame() {
+        for (key, &held) in keypad.iter().enumerate() {
+            replayed.set_key(key, held);
+        }
+        replayed.run_cycles(cycles).unwrap();
+    }
+
+    assert_eq!(replayed.v[1], 4 * 6, "every press was replayed");
+    assert_eq!(replayed.display_buffer(), recorded.display_buffer());
+}
+
+// Remembers every beep call, to check when the buzzer is switched
+#[derive(Default)]
+struct BeepLog(Vec<bool>);
+
+impl AudioBackend for BeepLog {
+    fn beep(&mut self, on: bool) {
+        self.0.push(on);
+    }
+
+    fn set_tone(&mut self, _hz: f32) {}
+
+    fn set_volume(&mut self, _volume: f32) {}
+}
+
+#[test]
+fn buzzer_beeps_while_the_sound_timer_runs() {
+    let program = assemble(
+        "
+        LD V0, 3
+        LD ST, V0
+        JP 0x204
+        ",
+    )
+    .unwrap();
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&program).unwrap();
+    let mut log = BeepLog::default();
+    for _ in 0..5 {
+        chippy.run_frame().unwrap();
+        buzzer::play_sound(&mut log, &chippy);
+        buzzer::play_sound(&mut NullAudio, &chippy);
+    }
+    // Set to 3 during the first frame, which sounds along with the two after it
+    assert_eq!(log.0, [true, true, true, false, false]);
+}
+
+#[test]
+fn exit_opcode_halts_without_advancing() {
+    let program = assemble(
+        "
+        LD V0, 1
+        EXIT
+        LD V0, 2
+        ",
+    )
+    .unwrap();
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&program).unwrap();
+    assert_eq!(chippy.run_frame().unwrap(), StepResult::Halt);
+    assert_eq!(chippy.pc, 0x202);
+    assert!(chippy.exited());
+    // Running on stays at the exit instead of falling through to the next instruction
+    assert_eq!(chippy.step().unwrap(), StepResult::Halt);
+    assert_eq!(chippy.pc, 0x202);
+    assert_eq!(chippy.v[0], 1);
+    assert_eq!(mnemonic(0x00FD), "EXIT");
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn grid_overlay_is_only_drawn_when_enabled() {
+    use chippy::chippy::frontend::Frontend;
+    use sdl2::rect::{Point, Rect};
+
+    let destination = Rect::new(0, 0, 640, 320);
+    assert!(Frontend::grid_lines(false, destination, 64, 32).is_empty());
+
+    // One line between each pair of neighbouring columns and rows, 10 window pixels apart
+    let lines = Frontend::grid_lines(true, destination, 64, 32);
+    assert_eq!(lines.len(), 63 + 31);
+    assert_eq!(lines[0], (Point::new(10, 0), Point::new(10, 319)));
+    assert_eq!(lines[63], (Point::new(0, 10), Point::new(639, 10)));
+}
+
+// A small ROM and the same ROM as written by `gzip -9`, which uses the fixed Huffman codes
+const GZIP_ROM: [u8; 60] = [
+    0x00, 0xE0, 0xA2, 0x0A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+    0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xF0, 0x90,
+    0xF0, 0x90, 0x90, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xF0, 0x90, 0xF0,
+    0x90, 0x90, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xF0, 0x90, 0xF0, 0x90, 0x90,
+];
+const GZIP_ROM_FIXED: [u8; 41] = [
+    0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x63, 0x78, 0xB0, 0x88, 0x2B, 0x81,
+    0x27, 0x91, 0xE3, 0x82, 0xFC, 0x7F, 0x06, 0x4C, 0xF8, 0x61, 0xC2, 0x87, 0x09, 0x13, 0x70, 0x12,
+    0x00, 0xCC, 0x62, 0x49, 0x01, 0x3C, 0x00, 0x00, 0x00,
+];
+
+#[test]
+fn gzip_inflates_every_block_type() {
+    assert_eq!(
+        gzip::decompress(&gzip::compress(&GZIP_ROM)).unwrap(),
+        GZIP_ROM
+    );
+    assert_eq!(gzip::decompress(&GZIP_ROM_FIXED).unwrap(), GZIP_ROM);
+
+    // Skewed random bytes, which `gzip -9` gives a dynamic Huffman block
+    let dynamic = [
+        0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x05, 0xC1, 0x01, 0x01, 0x00,
+        0x00, 0x08, 0xC3, 0x20, 0xA2, 0x2E, 0x92, 0x51, 0x1F, 0x41, 0x60, 0xAA, 0x23, 0x77, 0x1A,
+        0x7B, 0x8F, 0x78, 0x5F, 0x38, 0x14, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(
+        gzip::decompress(&dynamic).unwrap(),
+        [
+            0x00, 0x00, 0xF0, 0x00, 0x60, 0x60, 0x60, 0x90, 0x00, 0x00, 0x60, 0x00, 0x90, 0x90,
+            0x00, 0x60, 0xF0, 0x00, 0x00, 0xF0
+        ]
+    );
+
+    let mut corrupt = GZIP_ROM_FIXED;
+    corrupt[20] ^= 0x10;
+    assert!(gzip::decompress(&corrupt).is_err());
+    assert!(gzip::decompress(&GZIP_ROM_FIXED[..30]).is_err());
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn load_game_unpacks_gzipped_roms() {
+    // Recognised by the extension and by the gzip header alone
+    for (name, contents) in [
+        ("chippy-gzip-test.ch8.gz", gzip::compress(&GZIP_ROM)),
+        ("chippy-gzip-test.ch8", GZIP_ROM_FIXED.to_vec()),
+    ] {
+        let path = env::temp_dir().join(name);
+        fs::write(&path, contents).unwrap();
+        let path = path.to_str().unwrap();
+        let mut chippy = Chippy::new();
+        let loaded = chippy.load_game(path);
+        fs::remove_file(path).unwrap();
+        loaded.unwrap();
+        assert_eq!(chippy.memory[0x200..0x200 + GZIP_ROM.len()], GZIP_ROM);
+    }
+}
+
+#[test]
+fn short_beeps_last_the_minimum_duration() {
+    let beeps = |chippy: &mut Chippy| {
+        let mut log = BeepLog::default();
+        chippy.sound_timer = 1;
+        for _ in 0..5 {
+            chippy.tick_timers();
+            buzzer::play_sound(&mut log, chippy);
+        }
+        log.0
+    };
+
+    let mut chippy = Chippy::new();
+    chippy.min_beep_frames = 3;
+    assert_eq!(beeps(&mut chippy), [true, true, true, false, false]);
+    assert_eq!(chippy.sound_timer, 0);
+
+    // Below the threshold the timer still counts down, but silently
+    chippy.beep_threshold = 2;
+    assert_eq!(beeps(&mut chippy), [false; 5]);
+}
+
+#[test]
+fn sound_timer_below_the_threshold_stays_silent() {
+    let beeps = |sound_timer: u8| {
+        let mut chippy = Chippy::new();
+        chippy.beep_threshold = 3;
+        chippy.sound_timer = sound_timer;
+        let mut log = BeepLog::default();
+        for _ in 0..5 {
+            chippy.tick_timers();
+            buzzer::play_sound(&mut log, &chippy);
+        }
+        log.0
+    };
+    assert_eq!(beeps(2), [false; 5]);
+    assert_eq!(beeps(3), [true, true, true, false, false]);
+}
+
+#[test]
+fn sound_timer_of_one_beeps_for_a_frame() {
+    let mut chippy = Chippy::new();
+    chippy.min_beep_frames = 0;
+    chippy.sound_timer = 1;
+    chippy.tick_timers();
+    assert!(chippy.beeping());
+    chippy.tick_timers();
+    assert!(!chippy.beeping());
+}
+
+#[test]
+fn run_until_halt_counts_the_cycles_before_the_halt() {
+    // Counts V0 down from 5, two instructions per pass, then jumps to itself
+    let program = assemble(
+        "
+        LD V0, 5
+        ADD V0, 0xFF
+        SE V0, 0
+        JP 0x202
+        JP 0x208
+        ",
+    )
+    .unwrap();
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&program).unwrap();
+    // LD, five ADD and SE pairs, four taken JPs back
+    assert_eq!(chippy.run_until_halt(1000).unwrap(), 1 + 5 * 2 + 4);
+    assert_eq!(chippy.pc, 0x208);
+    // Detection is only switched on for the run
+    assert!(!chippy.halt_on_self_jump);
+
+    let mut chippy = Chippy::new();
+    chippy
+        .load_bytes(&assemble("ADD V0, 1\nJP 0x200").unwrap())
+        .unwrap();
+    let error = chippy.run_until_halt(100).unwrap_err();
+    assert!(error.contains("100 cycles"), "{}", error);
+}
+
+#[test]
+fn cheats_are_reapplied_every_frame() {
+    // Keeps storing 0 at 0x300, like a game resetting a counter
+    let program = assemble(
+        "
+        LD V0, 0
+        LD I, 0x300
+        LD [I], V0
+        JP 0x202
+        ",
+    )
+    .unwrap();
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&program).unwrap();
+
+    chippy.poke(0x300, 7);
+    assert_eq!(chippy.peek(0x300), 7);
+    for _ in 0..3 {
+        chippy.step().unwrap();
+    }
+    assert_eq!(chippy.peek(0x300), 0);
+
+    chippy.add_cheat(0x300, 9).unwrap();
+    assert_eq!(chippy.peek(0x300), 9);
+    for _ in 0..3 {
+        chippy.step().unwrap();
+    }
+    assert_eq!(chippy.peek(0x300), 0);
+    // The program's write is undone at the end of the frame
+    chippy.run_frame().unwrap();
+    assert_eq!(chippy.peek(0x300), 9);
+
+    chippy.remove_cheat(0x300);
+    chippy.run_frame().unwrap();
+    assert_eq!(chippy.peek(0x300), 0);
+
+    // Addresses are checked against the memory size
+    assert!(chippy.add_cheat(0x1000, 1).is_err());
+    chippy.poke(0x1000, 1);
+    assert_eq!(chippy.peek(0x1000), 0);
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn audio_pattern_plays_its_bits_at_the_pitch_rate() {
+    use chippy::chippy::audio::{SharedF32, Square};
+    use sdl2::audio::AudioCallback;
+
+    let pattern = [
+        0xF0, 0x0F, 0xAA, 0x55, 0x00, 0xFF, 0x81, 0x7E, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE,
+        0xF0,
+    ];
+    let mut source = "
+        LD I, 0x20E
+        AUDIO
+        LD V1, 112 ; pitch 112 plays 8000 bits per second
+        PITCH V1
+        LD V0, K
+        JP 0x20A
+        DW 0
+        "
+    .to_string();
+    for byte in pattern {
+        source.push_str(&format!("DB {}\n", byte));
+    }
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&assemble(&source).unwrap()).unwrap();
+
+    // Waiting for a key leaves the pattern and pitch alone
+    chippy.run_frame().unwrap();
+    chippy.set_key(5, true);
+    chippy.run_frame().unwrap();
+    chippy.set_key(5, false);
+    chippy.run_frame().unwrap();
+    assert_eq!(chippy.pc, 0x20A);
+    assert_eq!(chippy.audio_pattern(), Some(pattern));
+    assert_eq!(chippy.playback_rate(), 8000.0);
+
+    // At a sample rate of 8000Hz every sample is the next bit, wrapping after 128
+    let mut square = Square {
+        phase_inc: 0.0,
+        phase: 0.0,
+        tone: SharedF32::new(440.0),
+        sample_rate: 8000.0,
+        volume: SharedF32::new(1.0),
+        pattern: chippy.audio_pattern(),
+        pattern_rate: chippy.playback_rate(),
+        pattern_position: 0.0,
+        recording: None,
+    };
+    let mut samples = [0.0; 256];
+    square.callback(&mut samples);
+    for (position, &sample) in samples.iter().enumerate() {
+        let bit = position % 128;
+        let expected = if pattern[bit / 8] >> (7 - bit % 8) & 1 == 1 {
+            0.5
+        } else {
+            -0.5
+        };
+        assert_eq!(sample, expected, "sample {}", position);
+    }
+}
+
+// Assembles `source` and loads it at 0x200
+fn load_program(source: &str) -> Chippy {
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&assemble(source).unwrap()).unwrap();
+    chippy
+}
+
+fn run_steps(chippy: &mut Chippy, steps: usize) {
+    for _ in 0..steps {
+        chippy.step().unwrap();
+    }
+}
+
+#[test]
+fn clear_screen_blanks_the_display() {
+    let mut chippy = load_program(
+        "
+        LD F, V0
+        DRW V0, V0, 5
+        CLS
+        ",
+    );
+    run_steps(&mut chippy, 2);
+    assert!(chippy.display_buffer().iter().any(|&pixel| pixel != 0));
+    run_steps(&mut chippy, 1);
+    assert!(chippy.display_buffer().iter().all(|&pixel| pixel == 0));
+}
+
+#[test]
+fn return_pops_the_pushed_address() {
+    let mut chippy = load_program("RET");
+    chippy.stack[0] = 0x2A0;
+    chippy.sp = 1;
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.pc, 0x2A0);
+    assert_eq!(chippy.sp, 0);
+}
+
+#[test]
+fn jump_lands_exactly_on_its_target() {
+    let mut chippy = load_program("JP 0x208");
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.pc, 0x208);
+}
+
+#[test]
+fn return_resumes_after_the_call() {
+    let mut chippy = load_program(
+        "
+        CALL 0x206
+        LD V0, 1
+        JP 0x204
+        RET
+        ",
+    );
+    run_steps(&mut chippy, 1);
+    assert_eq!((chippy.pc, chippy.sp), (0x206, 1));
+    run_steps(&mut chippy, 1);
+    assert_eq!((chippy.pc, chippy.sp), (0x202, 0));
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.v[0], 1);
+}
+
+#[test]
+fn reset_restores_the_start_state() {
+    let mut chippy = load_program(
+        "
+        LD VA, 5
+        LD V3, 7
+        ",
+    );
+    run_steps(&mut chippy, 2);
+    assert_eq!(chippy.pc, 0x204);
+    chippy.reset();
+    assert_eq!(chippy.pc, 0x200);
+    assert_eq!(chippy.v, [0; 16]);
+}
+
+#[test]
+fn delay_timer_reaches_zero_on_the_tenth_tick() {
+    let mut chippy = Chippy::new();
+    chippy.delay_timer = 10;
+    for _ in 0..9 {
+        chippy.tick_timers();
+    }
+    assert_eq!(chippy.delay_timer, 1);
+    chippy.tick_timers();
+    assert_eq!(chippy.delay_timer, 0);
+    // And stays there
+    chippy.tick_timers();
+    assert_eq!(chippy.delay_timer, 0);
+}
+
+#[test]
+fn bcd_stores_hundreds_tens_and_ones() {
+    for (value, digits) in [
+        (0, [0, 0, 0]),
+        (9, [0, 0, 9]),
+        (10, [0, 1, 0]),
+        (99, [0, 9, 9]),
+        (100, [1, 0, 0]),
+        (128, [1, 2, 8]),
+        (255, [2, 5, 5]),
+    ] {
+        let mut chippy = load_program(
+            "
+            LD I, 0x300
+            LD B, V0
+            ",
+        );
+        chippy.v[0] = value;
+        run_steps(&mut chippy, 2);
+        assert_eq!(chippy.memory[0x300..0x303], digits, "BCD of {}", value);
+    }
+}
+
+#[test]
+fn load_bytes_accepts_roms_up_to_the_end_of_memory() {
+    // 4096 bytes of memory less the 0x200 below the load address
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&[0xAB; 3584]).unwrap();
+    assert_eq!(chippy.memory[0xFFF], 0xAB);
+
+    let error = chippy.load_bytes(&[0xAB; 3585]).unwrap_err();
+    assert!(error.contains("ROM too large: 3585 bytes"), "{}", error);
+}
+
+#[test]
+fn load_bytes_runs_a_rom_from_memory() {
+    let mut chippy = Chippy::new();
+    // LD V0, 0x2A and ADD V0, 1
+    chippy.load_bytes(&[0x60, 0x2A, 0x70, 0x01]).unwrap();
+    assert_eq!(chippy.pc, 0x200);
+    chippy.step().unwrap();
+    assert_eq!((chippy.pc, chippy.v[0]), (0x202, 0x2A));
+    chippy.step().unwrap();
+    assert_eq!((chippy.pc, chippy.v[0]), (0x204, 0x2B));
+}
+
+#[test]
+fn step_executes_one_instruction_at_a_time() {
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&[0x6A, 0x05, 0x7A, 0x03]).unwrap();
+    chippy.step().unwrap();
+    assert_eq!(chippy.v[0xA], 5);
+    chippy.step().unwrap();
+    assert_eq!(chippy.v[0xA], 8);
+}
+
+#[test]
+fn memory_increment_i_quirk_moves_i_past_the_stored_registers() {
+    for (memory_increment_i, expected_i) in [(true, 0x304), (false, 0x300)] {
+        let mut chippy = load_program(
+            "
+            LD I, 0x300
+            LD [I], V3
+            ",
+        );
+        chippy.quirks.memory_increment_i = memory_increment_i;
+        chippy.v[..4].copy_from_slice(&[1, 2, 3, 4]);
+        run_steps(&mut chippy, 2);
+        assert_eq!(chippy.i, expected_i);
+        assert_eq!(chippy.memory[0x300..0x305], [1, 2, 3, 4, 0]);
+    }
+}
+
+#[test]
+fn shift_vy_into_vx_quirk_picks_the_shifted_register() {
+    // Vx = 0x0E and Vy = 0x81 have different low and high bits, so VF tells them apart too
+    for (shift_vy_into_vx, shr, shl) in
+        [(true, (0x40, 1), (0x02, 1)), (false, (0x07, 0), (0x1C, 0))]
+    {
+        for (source, expected) in [("SHR V1, V2", shr), ("SHL V1, V2", shl)] {
+            let mut chippy = load_program(source);
+            chippy.quirks.shift_vy_into_vx = shift_vy_into_vx;
+            chippy.v[1] = 0x0E;
+            chippy.v[2] = 0x81;
+            run_steps(&mut chippy, 1);
+            assert_eq!(
+                (chippy.v[1], chippy.v[0xF]),
+                expected,
+                "{} with shift_vy_into_vx {}",
+                source,
+                shift_vy_into_vx
+            );
+        }
+    }
+}
+
+#[test]
+fn bxnn_quirk_picks_the_offset_register() {
+    for (bxnn_uses_vx, target) in [(false, 0x330), (true, 0x340)] {
+        let mut chippy = load_program("JP V0, 0x320");
+        chippy.quirks.bxnn_uses_vx = bxnn_uses_vx;
+        chippy.v[0] = 0x10;
+        chippy.v[3] = 0x20;
+        run_steps(&mut chippy, 1);
+        assert_eq!(chippy.pc, target);
+    }
+
+    // A target past the end of memory wraps instead of running off it
+    let mut chippy = load_program("JP V0, 0xFFF");
+    chippy.v[0] = 0xFF;
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.pc, 0x0FE);
+}
+
+#[test]
+fn flag_is_written_after_the_result_when_vf_is_the_target() {
+    // The results would be 0x01, 0xFF, 0x02, 0x01 and 0x02, the flags differ from each
+    for (source, vf, v1, flag) in [
+        ("ADD VF, V1", 0xFF, 0x02, 1),
+        ("SUB VF, V1", 0x01, 0x02, 0),
+        ("SUBN VF, V1", 0x01, 0x03, 1),
+        ("SHR VF", 0x02, 0x00, 0),
+        ("SHL VF", 0x81, 0x00, 1),
+    ] {
+        let mut chippy = load_program(source);
+        chippy.v[0xF] = vf;
+        chippy.v[1] = v1;
+        run_steps(&mut chippy, 1);
+        assert_eq!(chippy.v[0xF], flag, "{}", source);
+    }
+}
+
+// Coordinates of every lit pixel, row by row
+fn lit_pixels(chippy: &Chippy) -> Vec<(usize, usize)> {
+    let width = chippy.width();
+    (0..chippy.height())
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|&(x, y)| chippy.display_buffer()[y * width + x] != 0)
+        .collect()
+}
+
+// Draws a solid 8-wide row at x = 60, running 4 pixels past the right edge
+fn draw_across_the_right_edge(clip_sprites: bool) -> Vec<(usize, usize)> {
+    let mut chippy = load_program(
+        "
+        LD V0, 60
+        LD V1, 0
+        LD I, 0x20A
+        DRW V0, V1, 1
+        JP 0x208
+        DB 0xFF
+        ",
+    );
+    chippy.quirks.clip_sprites = clip_sprites;
+    run_steps(&mut chippy, 4);
+    lit_pixels(&chippy)
+}
+
+#[test]
+fn sprites_clip_at_the_right_edge() {
+    assert_eq!(
+        draw_across_the_right_edge(true),
+        [(60, 0), (61, 0), (62, 0), (63, 0)]
+    );
+}
+
+#[test]
+fn sprites_wrap_to_the_left_edge_without_clipping() {
+    assert_eq!(
+        draw_across_the_right_edge(false),
+        [
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 0),
+            (60, 0),
+            (61, 0),
+            (62, 0),
+            (63, 0)
+        ]
+    );
+}
+
+// Draws a single pixel at (x, 2), then runs `scroll`
+fn scroll_pixel(x: u8, scroll: &str) -> Vec<(usize, usize)> {
+    let mut chippy = load_program(&format!(
+        "
+        LD V0, {}
+        LD V1, 2
+        LD I, 0x20C
+        DRW V0, V1, 1
+        {}
+        JP 0x20A
+        DB 0x80
+        ",
+        x, scroll
+    ));
+    run_steps(&mut chippy, 5);
+    lit_pixels(&chippy)
+}
+
+#[test]
+fn scrolls_move_the_display_by_their_distance() {
+    assert_eq!(scroll_pixel(8, "SCD 4"), [(8, 6)]);
+    assert_eq!(scroll_pixel(8, "SCD 15"), [(8, 17)]);
+    assert_eq!(scroll_pixel(8, "SCR"), [(12, 2)]);
+    assert_eq!(scroll_pixel(8, "SCL"), [(4, 2)]);
+    // Pixels scrolled off an edge are gone rather than wrapped
+    assert_eq!(scroll_pixel(2, "SCL"), []);
+    assert_eq!(scroll_pixel(62, "SCR"), []);
+}
+
+#[test]
+fn horizontal_scrolls_use_the_hires_width() {
+    let mut chippy = load_program(
+        "
+        HIGH
+        LD V0, 120
+        LD V1, 2
+        LD I, 0x20E
+        DRW V0, V1, 1
+        SCR
+        JP 0x20C
+        DB 0x80
+        ",
+    );
+    run_steps(&mut chippy, 6);
+    assert_eq!(chippy.display_buffer().len(), 128 * 64);
+    assert_eq!(lit_pixels(&chippy), [(124, 2)]);
+
+    // The next scroll pushes it past column 127
+    chippy.pc = 0x20A;
+    run_steps(&mut chippy, 1);
+    assert_eq!(lit_pixels(&chippy), []);
+}
+
+#[test]
+fn hires_toggle_resizes_the_display() {
+    let mut chippy = load_program(
+        "
+        HIGH
+        LD V0, 100
+        LD V1, 40
+        LD I, 0x20E
+        DRW V0, V1, 1
+        LOW
+        JP 0x20C
+        DB 0x80
+        ",
+    );
+    assert_eq!(chippy.display_buffer().len(), 64 * 32);
+    run_steps(&mut chippy, 1);
+    assert_eq!((chippy.width(), chippy.height()), (128, 64));
+    assert_eq!(chippy.display_buffer().len(), 128 * 64);
+
+    // Rows are 128 pixels apart now, and coordinates past 64x32 are on screen
+    run_steps(&mut chippy, 4);
+    assert_eq!(lit_pixels(&chippy), [(100, 40)]);
+    assert_ne!(chippy.display_buffer()[40 * 128 + 100], 0);
+
+    run_steps(&mut chippy, 1);
+    assert_eq!((chippy.width(), chippy.height()), (64, 32));
+    assert_eq!(chippy.display_buffer().len(), 64 * 32);
+}
+
+#[test]
+fn big_sprites_draw_and_collide_across_both_bytes() {
+    // Each of the 16 rows lights the leftmost and rightmost column
+    let mut chippy = load_program(&format!(
+        "
+        LD I, 0x20E
+        LD V0, 0
+        LD V1, 15
+        DRW V0, V0, 0
+        DRW V1, V0, 0
+        DRW V0, V0, 0
+        JP 0x20C
+        {}
+        ",
+        "DB 0x80, 0x01\n".repeat(16)
+    ));
+    let columns = |columns: [usize; 2]| -> Vec<(usize, usize)> {
+        (0..16).flat_map(|y| columns.map(|x| (x, y))).collect()
+    };
+
+    run_steps(&mut chippy, 4);
+    assert_eq!(lit_pixels(&chippy), columns([0, 15]));
+    assert_eq!(chippy.v[0xF], 0);
+
+    // At x = 15 the left byte overlaps the first sprite's right column
+    run_steps(&mut chippy, 1);
+    assert_eq!(lit_pixels(&chippy), columns([0, 30]));
+    assert_eq!(chippy.v[0xF], 1);
+
+    // Back at x = 0 the left column collides, the right one is drawn again
+    run_steps(&mut chippy, 1);
+    assert_eq!(lit_pixels(&chippy), columns([15, 30]));
+    assert_eq!(chippy.v[0xF], 1);
+}
+
+#[test]
+fn planes_combine_into_a_colour_index() {
+    // One pixel each with plane 1, plane 2 and both selected. With both, plane 2's sprite
+    // follows plane 1's.
+    let mut chippy = load_program(
+        "
+        LD V0, 0
+        LD V1, 1
+        LD V2, 2
+        LD I, 0x216
+        PLANE 1
+        DRW V0, V3, 1
+        PLANE 2
+        DRW V1, V3, 1
+        PLANE 3
+        DRW V2, V3, 1
+        JP 0x214
+        DB 0x80, 0x80
+        ",
+    );
+    run_steps(&mut chippy, 10);
+    assert_eq!(chippy.display_buffer()[..4], [0b01, 0b10, 0b11, 0b00]);
+    assert_eq!(lit_pixels(&chippy), [(0, 0), (1, 0), (2, 0)]);
+}
+
+#[test]
+fn wait_for_key_completes_only_after_the_release() {
+    let mut chippy = load_program(
+        "
+        LD V0, K
+        LD V1, 1
+        ",
+    );
+    assert_eq!(chippy.step().unwrap(), StepResult::WaitingForKey);
+    chippy.tick_timers();
+
+    chippy.set_key(7, true);
+    for _ in 0..3 {
+        assert_eq!(chippy.step().unwrap(), StepResult::WaitingForKey);
+        assert_eq!(chippy.pc, 0x200);
+    }
+
+    chippy.set_key(7, false);
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    assert_eq!((chippy.pc, chippy.v[0]), (0x202, 7));
+    run_steps(&mut chippy, 1);
+    assert_eq!((chippy.pc, chippy.v[1]), (0x204, 1));
+}
+
+#[test]
+fn save_state_round_trips_the_machine() {
+    let mut chippy = load_program(
+        "
+        LD V0, 12
+        LD F, V0
+        DRW V0, V0, 5
+        CALL 0x20A
+        LD V1, 1
+        LD V2, 2
+        ",
+    );
+    run_steps(&mut chippy, 5);
+    chippy.delay_timer = 30;
+    chippy.sound_timer = 4;
+    chippy.set_key(0xB, true);
+    chippy.poke(0x300, 0x5A);
+    let saved = chippy.save_state();
+
+    chippy.reset();
+    chippy.release_all_keys();
+    assert_ne!(chippy.save_state(), saved);
+
+    chippy.load_state(&saved).unwrap();
+    assert_eq!(chippy.save_state(), saved);
+    assert_eq!((chippy.pc, chippy.sp), (0x20C, 1));
+    assert_eq!(chippy.call_stack(), [0x208]);
+    assert_eq!((chippy.v[0], chippy.v[2]), (12, 2));
+    assert_eq!((chippy.delay_timer, chippy.sound_timer), (30, 4));
+    assert!(chippy.keypad_state()[0xB]);
+    assert_eq!(chippy.peek(0x300), 0x5A);
+    assert!(chippy.display_buffer().iter().any(|&pixel| pixel != 0));
+}
+
+#[test]
+fn load_state_rejects_damaged_blobs() {
+    let mut chippy = load_program("LD V0, 1");
+    let saved = chippy.save_state();
+    run_steps(&mut chippy, 1);
+
+    assert!(chippy.load_state(&saved[..saved.len() - 1]).is_err());
+    let mut bad_magic = saved.clone();
+    bad_magic[0] = b'X';
+    assert!(chippy.load_state(&bad_magic).is_err());
+    // Neither touched the machine
+    assert_eq!((chippy.pc, chippy.v[0]), (0x202, 1));
+}
+
+#[test]
+fn disassemble_lists_addresses_and_mnemonics() {
+    let mut chippy = Chippy::new();
+    chippy
+        .load_bytes(&[
+            0x60, 0x0A, 0xD0, 0x15, 0x23, 0x00, 0x50, 0x01, 0x00, 0xE0, 0xF2, 0x33,
+        ])
+        .unwrap();
+    assert_eq!(
+        chippy.disassemble(0x200, 6),
+        [
+            (0x200, "LD V0, 0x0A".to_string()),
+            (0x202, "DRW V0, V1, 5".to_string()),
+            (0x204, "CALL 0x300".to_string()),
+            (0x206, "DW 0x5001".to_string()),
+            (0x208, "CLS".to_string()),
+            (0x20A, "LD B, V2".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn stack_holds_sixteen_calls() {
+    let mut chippy = load_program("CALL 0x200");
+    chippy.strict = true;
+    run_steps(&mut chippy, 16);
+    assert_eq!(chippy.sp, 16);
+    assert_eq!(chippy.call_stack(), [0x202; 16]);
+
+    let error = chippy.step().unwrap_err();
+    assert!(error.contains("Stack overflow"), "{}", error);
+
+    // Outside strict mode the 17th call is dropped and flagged
+    let mut chippy = load_program("CALL 0x200");
+    run_steps(&mut chippy, 17);
+    assert_eq!(chippy.sp, 16);
+    assert!(chippy.stack_fault());
+}
+
+#[test]
+fn add_to_index_wraps_and_only_sets_vf_with_the_quirk() {
+    for (i, value, sum) in [(0x300, 0x10, 0x310), (0xFFF, 0x02, 0x001)] {
+        let mut chippy = load_program("ADD I, V0");
+        chippy.i = i;
+        chippy.v[0] = value;
+        chippy.v[0xF] = 0xAA;
+        run_steps(&mut chippy, 1);
+        assert_eq!((chippy.i, chippy.v[0xF]), (sum, 0xAA));
+    }
+
+    for (i, flag) in [(0x300, 0), (0xFFF, 1)] {
+        let mut chippy = load_program("ADD I, V0");
+        chippy.quirks.i_overflow_sets_vf = true;
+        chippy.i = i;
+        chippy.v[0] = 0x02;
+        chippy.v[0xF] = 0xAA;
+        run_steps(&mut chippy, 1);
+        assert_eq!(chippy.v[0xF], flag);
+    }
+}
+
+#[test]
+fn font_character_points_at_the_digit_sprite() {
+    let mut chippy = load_program("LD F, V0");
+    chippy.v[0] = 0x1A;
+    run_steps(&mut chippy, 1);
+    // Only the low nibble picks the digit
+    assert_eq!(chippy.i, 0x050 + 0xA * 5);
+}
+
+#[test]
+fn big_font_character_points_at_the_ten_byte_sprite() {
+    let mut chippy = load_program(
+        "
+        LD F, V0
+        LD HF, V1
+        ",
+    );
+    chippy.v[0] = 7;
+    chippy.v[1] = 3;
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.i, 0x073);
+    assert_eq!(chippy.memory[0x073..0x078], FONT[35..40]);
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.i, 0x0A0 + 3 * 10);
+}
+
+#[test]
+fn seeded_random_numbers_are_repeatable() {
+    let seeded = || {
+        let mut chippy = load_program(
+            "
+            RND V0, 0xFF
+            RND V1, 0xFF
+            RND V2, 0xFF
+            RND V3, 0x0F
+            ",
+        );
+        chippy.set_seed(42);
+        run_steps(&mut chippy, 4);
+        chippy
+    };
+    let chippy = seeded();
+    #[cfg(feature = "rand")]
+    assert_eq!(chippy.v[..4], [162, 99, 125, 3]);
+    #[cfg(not(feature = "rand"))]
+    assert_eq!(chippy.v[..4], [211, 211, 150, 12]);
+    assert_eq!(seeded().v, chippy.v);
+}
+
+#[test]
+fn breakpoints_stop_before_the_instruction() {
+    let mut chippy = load_program(
+        "
+        LD V0, 1
+        LD V1, 2
+        LD V2, 3
+        LD V3, 4
+        ",
+    );
+    chippy.add_breakpoint(0x204);
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    assert_eq!(chippy.step().unwrap(), StepResult::Breakpoint(0x204));
+    assert_eq!((chippy.pc, chippy.v[2]), (0x204, 0));
+
+    // Stepping again resumes from the breakpoint
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    assert_eq!((chippy.pc, chippy.v[2]), (0x206, 3));
+
+    chippy.reset();
+    chippy.clear_breakpoints();
+    run_steps(&mut chippy, 4);
+    assert_eq!(chippy.v[3], 4);
+}
+
+#[test]
+fn step_reports_what_happened() {
+    let mut chippy = load_program("LD V0, 1");
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+
+    let mut chippy = load_program("LD V0, K");
+    assert_eq!(chippy.step().unwrap(), StepResult::WaitingForKey);
+
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&[0xE0, 0xFF]).unwrap();
+    assert_eq!(chippy.step().unwrap(), StepResult::UnknownOpcode(0xE0FF));
+}
+
+#[test]
+fn jump_to_itself_halts_when_enabled() {
+    for (halt_on_self_jump, result) in [(true, StepResult::Halt), (false, StepResult::Continue)] {
+        let mut chippy = load_program(
+            "
+            LD V0, 1
+            JP 0x202
+            ",
+        );
+        chippy.halt_on_self_jump = halt_on_self_jump;
+        assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+        assert_eq!(chippy.step().unwrap(), result);
+        assert_eq!(chippy.pc, 0x202);
+    }
+}
+
+#[test]
+fn drawing_from_the_end_of_memory_wraps_the_sprite_read() {
+    let mut chippy = load_program("DRW V0, V0, 5");
+    chippy.i = 0xFFE;
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.i, 0xFFE);
+    assert_eq!(chippy.pc, 0x202);
+}
+
+#[test]
+fn drawing_leaves_i_alone() {
+    let mut chippy = load_program(
+        "
+        LD V1, 8
+        LD F, V2
+        DRW V0, V0, 5
+        DRW V1, V0, 5
+        ",
+    );
+    chippy.v[2] = 0xE;
+    run_steps(&mut chippy, 3);
+    let i = chippy.i;
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.i, i);
+
+    // The second copy came from the same sprite data
+    for y in 0..5 {
+        for x in 0..8 {
+            assert_eq!(chippy.pixel(x, y), chippy.pixel(x + 8, y));
+        }
+    }
+    assert!(chippy.pixel(0, 0));
+}
+
+#[test]
+fn wait_for_key_ignores_keys_held_from_before() {
+    let mut chippy = load_program("LD V0, K");
+    chippy.set_key(2, true);
+    chippy.tick_timers();
+
+    chippy.set_key(9, true);
+    assert_eq!(chippy.step().unwrap(), StepResult::WaitingForKey);
+    chippy.set_key(9, false);
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    assert_eq!(chippy.v[0], 9);
+}
+
+#[test]
+fn roms_load_into_larger_memory() {
+    let mut chippy = Chippy::new();
+    chippy.set_memory_size(65536).unwrap();
+    chippy.load_address = 0x2000;
+    let rom = assemble("LD V0, 0x42").unwrap();
+    chippy.load_bytes(&rom).unwrap();
+    assert_eq!(chippy.mem_size(), 65536);
+    assert_eq!(chippy.pc, 0x2000);
+    assert_eq!(chippy.memory[0x2000..0x2002], rom[..]);
+    run_steps(&mut chippy, 1);
+    assert_eq!((chippy.pc, chippy.v[0]), (0x2002, 0x42));
+
+    assert!(chippy.set_memory_size(5000).is_err());
+    assert!(chippy.set_memory_size(0x20000).is_err());
+}
+
+#[test]
+fn long_load_sets_a_sixteen_bit_index() {
+    let mut chippy = Chippy::new();
+    chippy.set_memory_size(65536).unwrap();
+    chippy.load_bytes(&[0xF0, 0x00, 0x0A, 0x00]).unwrap();
+    run_steps(&mut chippy, 1);
+    assert_eq!((chippy.i, chippy.pc), (0x0A00, 0x204));
+}
+
+#[test]
+fn register_ranges_store_and_load_in_either_direction() {
+    for (source, stored) in [
+        ("LD [I], V2-V5", [1, 2, 3, 4]),
+        ("LD [I], V5-V2", [4, 3, 2, 1]),
+    ] {
+        let mut chippy = load_program(&format!("LD I, 0x300\n{}", source));
+        chippy.v[2..6].copy_from_slice(&[1, 2, 3, 4]);
+        run_steps(&mut chippy, 2);
+        assert_eq!(chippy.memory[0x300..0x304], stored, "{}", source);
+        assert_eq!(chippy.i, 0x300);
+    }
+
+    for (source, loaded) in [
+        ("LD V8-VB, [I]", [1, 2, 3, 4]),
+        ("LD VB-V8, [I]", [4, 3, 2, 1]),
+    ] {
+        let mut chippy = load_program(&format!("LD I, 0x300\n{}", source));
+        chippy.memory[0x300..0x304].copy_from_slice(&[1, 2, 3, 4]);
+        run_steps(&mut chippy, 2);
+        assert_eq!(chippy.v[8..12], loaded, "{}", source);
+        assert_eq!(chippy.i, 0x300);
+    }
+}
+
+#[test]
+fn step_back_undoes_steps() {
+    let mut chippy = load_program(
+        "
+        LD V0, 1
+        LD V0, 2
+        LD V0, 3
+        ",
+    );
+    chippy.set_rewind_capacity(64);
+    run_steps(&mut chippy, 1);
+    let after_first = chippy.save_state();
+    run_steps(&mut chippy, 2);
+    assert_eq!(chippy.v[0], 3);
+
+    assert!(chippy.step_back());
+    assert!(chippy.step_back());
+    assert_eq!(chippy.save_state(), after_first);
+    assert_eq!((chippy.pc, chippy.v[0]), (0x202, 1));
+
+    assert!(chippy.step_back());
+    assert!(!chippy.step_back());
+    assert_eq!(chippy.pc, 0x200);
+}
+
+#[test]
+fn machine_code_calls_are_skipped() {
+    let mut chippy = load_program(
+        "
+        SYS 0x123
+        LD V0, 7
+        ",
+    );
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    assert_eq!(chippy.pc, 0x202);
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.v[0], 7);
+}
+
+#[test]
+fn machine_code_calls_ending_in_e0_or_ee_are_not_clear_or_return() {
+    // 0x05E0 and 0x0AEE share their low byte with 00E0 and 00EE but are 0nnn calls
+    let mut chippy = Chippy::new();
+    chippy
+        .load_bytes(&[0xF0, 0x29, 0xD0, 0x05, 0x05, 0xE0, 0x0A, 0xEE])
+        .unwrap();
+    chippy.stack[0] = 0x2A0;
+    chippy.sp = 1;
+    run_steps(&mut chippy, 3);
+    assert!(chippy.display_buffer().iter().any(|&pixel| pixel != 0));
+    run_steps(&mut chippy, 1);
+    assert_eq!((chippy.pc, chippy.sp), (0x208, 1));
+}
+
+#[test]
+fn scroll_down_needs_the_full_00cn_opcode() {
+    // 0x0AC3 and 0x03C4 are 0nnn calls, only 0x00C3 scrolls
+    for (opcode, scrolled) in [(0x0AC3u16, false), (0x03C4, false), (0x00C3, true)] {
+        let mut chippy = Chippy::new();
+        let [high, low] = opcode.to_be_bytes();
+        chippy
+            .load_bytes(&[0xF0, 0x29, 0xD0, 0x01, high, low])
+            .unwrap();
+        run_steps(&mut chippy, 3);
+        let row = if scrolled { 3 } else { 0 };
+        assert!(chippy.pixel(0, row), "{:#06X}", opcode);
+    }
+}
+
+#[test]
+fn machine_code_call_ending_in_fd_does_not_exit() {
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&[0x01, 0xFD, 0x00, 0xFD]).unwrap();
+    assert!(!chippy.exited());
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    assert_eq!(chippy.step().unwrap(), StepResult::Halt);
+    assert!(chippy.exited());
+}
+
+#[test]
+fn held_key_repeats_do_not_satisfy_wait_for_key() {
+    let mut chippy = load_program(
+        "
+        LD V0, K
+        LD V1, K
+        ",
+    );
+    // Held since before the wait, with the key repeat setting it again every frame
+    chippy.set_key(5, true);
+    chippy.tick_timers();
+    for _ in 0..3 {
+        chippy.set_key(5, true);
+        assert_eq!(chippy.step().unwrap(), StepResult::WaitingForKey);
+        chippy.tick_timers();
+    }
+    chippy.set_key(5, false);
+    assert_eq!(chippy.step().unwrap(), StepResult::WaitingForKey);
+    chippy.tick_timers();
+
+    // A fresh press and release completes the first wait only
+    chippy.set_key(5, true);
+    assert_eq!(chippy.step().unwrap(), StepResult::WaitingForKey);
+    chippy.set_key(5, false);
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    assert_eq!((chippy.pc, chippy.v[0]), (0x202, 5));
+    assert_eq!(chippy.step().unwrap(), StepResult::WaitingForKey);
+    assert_eq!(chippy.pc, 0x202);
+}
+
+#[test]
+fn rpl_flags_save_and_restore_registers() {
+    let mut chippy = load_program(
+        "
+        LD R, V3
+        LD V0, 0
+        LD V1, 0
+        LD V2, 0
+        LD V3, 0
+        LD V4, 0
+        LD V3, R
+        ",
+    );
+    chippy.v[..5].copy_from_slice(&[10, 11, 12, 13, 14]);
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.rpl[..5], [10, 11, 12, 13, 0]);
+    run_steps(&mut chippy, 6);
+    // V4 wasn't saved, so it stays clobbered
+    assert_eq!(chippy.v[..5], [10, 11, 12, 13, 0]);
+}
+
+#[test]
+fn rpl_flags_stop_at_v7() {
+    // There are only 8 flags, higher registers are neither saved nor restored
+    let mut chippy = load_program(
+        "
+        LD R, VF
+        LD V9, R
+        ",
+    );
+    chippy.v = std::array::from_fn(|i| i as u8 + 1);
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.rpl, [1, 2, 3, 4, 5, 6, 7, 8]);
+    chippy.v = [0xAA; 16];
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.v[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(chippy.v[8..], [0xAA; 8]);
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn key_mappings_look_up_the_bound_chip8_key() {
+    use chippy::chippy::frontend::Frontend;
+    use sdl2::keyboard::Keycode;
+
+    // Every keypad key is bound to exactly one keyboard key by default
+    let mut mapping = Frontend::default_key_mapping();
+    Frontend::check_key_mapping(&mapping).unwrap();
+    let mut keys: Vec<usize> = mapping.values().copied().collect();
+    keys.sort_unstable();
+    assert_eq!(keys, (0..16).collect::<Vec<_>>());
+    assert_eq!(
+        Frontend::map_keycode_to_chip8_key(&mapping, Keycode::W),
+        Some(0x5)
+    );
+    assert_eq!(
+        Frontend::map_keycode_to_chip8_key(&mapping, Keycode::Up),
+        None
+    );
+
+    // Moving 5 from W to the up arrow
+    mapping.remove(&Keycode::W);
+    mapping.insert(Keycode::Up, 0x5);
+    Frontend::check_key_mapping(&mapping).unwrap();
+    assert_eq!(
+        Frontend::map_keycode_to_chip8_key(&mapping, Keycode::Up),
+        Some(0x5)
+    );
+    assert_eq!(
+        Frontend::map_keycode_to_chip8_key(&mapping, Keycode::W),
+        None
+    );
+
+    mapping.insert(Keycode::Down, 0x10);
+    assert!(Frontend::check_key_mapping(&mapping).is_err());
+}
+
+#[test]
+fn instruction_hook_sees_each_executed_opcode() {
+    let mut chippy = load_program(
+        "
+        LD V0, 2
+        CALL 0x206
+        JP 0x202
+        ADD V0, 1
+        RET
+        ",
+    );
+    let trace = Rc::new(RefCell::new(Vec::new()));
+    let log = Rc::clone(&trace);
+    chippy.set_instruction_hook(Some(Box::new(move |pc, opcode| {
+        log.borrow_mut().push((pc, opcode));
+    })));
+    run_steps(&mut chippy, 5);
+    assert_eq!(
+        *trace.borrow(),
+        [
+            (0x200, 0x6002),
+            (0x202, 0x2206),
+            (0x206, 0x7001),
+            (0x208, 0x00EE),
+            (0x204, 0x1202),
+        ]
+    );
+
+    chippy.set_instruction_hook(None);
+    run_steps(&mut chippy, 1);
+    assert_eq!(trace.borrow().len(), 5);
+}
+
+#[test]
+fn memory_hooks_see_stores_and_loads() {
+    let mut chippy = load_program(
+        "
+        LD I, 0x300
+        LD B, V0
+        LD [I], V1
+        LD V1, [I]
+        ",
+    );
+    chippy.quirks.memory_increment_i = false;
+    chippy.v[0] = 123;
+    chippy.v[1] = 0x42;
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    let reads = Rc::new(RefCell::new(Vec::new()));
+    let (write_log, read_log) = (Rc::clone(&writes), Rc::clone(&reads));
+    chippy.set_memory_write_hook(Some(Box::new(move |address, value| {
+        write_log.borrow_mut().push((address, value));
+    })));
+    chippy.set_memory_read_hook(Some(Box::new(move |address, value| {
+        read_log.borrow_mut().push((address, value));
+    })));
+
+    run_steps(&mut chippy, 2);
+    assert_eq!(*writes.borrow(), [(0x300, 1), (0x301, 2), (0x302, 3)]);
+    run_steps(&mut chippy, 1);
+    assert_eq!(writes.borrow()[3..], [(0x300, 123), (0x301, 0x42)]);
+    assert!(reads.borrow().is_empty());
+    run_steps(&mut chippy, 1);
+    assert_eq!(*reads.borrow(), [(0x300, 123), (0x301, 0x42)]);
+}
+
+#[test]
+fn unknown_opcodes_are_counted_or_refused_in_strict_mode() {
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&[0x80, 0x0F, 0xE0, 0xFF]).unwrap();
+    assert_eq!(chippy.step().unwrap(), StepResult::UnknownOpcode(0x800F));
+    assert_eq!(chippy.step().unwrap(), StepResult::UnknownOpcode(0xE0FF));
+    assert_eq!(chippy.unknown_opcode_count(), 2);
+    assert_eq!(chippy.pc, 0x204);
+
+    chippy.reset();
+    chippy.strict = true;
+    let error = chippy.step().unwrap_err();
+    assert!(error.contains("800F"), "{}", error);
+    assert_eq!(chippy.pc, 0x200);
+}
+
+#[test]
+fn display_wait_allows_one_draw_per_frame() {
+    let mut chippy = load_program(
+        "
+        DRW V0, V0, 1
+        DRW V0, V0, 1
+        ",
+    );
+    chippy.quirks.display_wait = true;
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    for _ in 0..3 {
+        assert_eq!(chippy.step().unwrap(), StepResult::WaitingForVblank);
+        assert_eq!(chippy.pc, 0x202);
+    }
+    chippy.tick_timers();
+    assert_eq!(chippy.step().unwrap(), StepResult::Continue);
+    assert_eq!(chippy.pc, 0x204);
+
+    // Without the quirk both draws run back to back
+    let mut chippy = load_program(
+        "
+        DRW V0, V0, 1
+        DRW V0, V0, 1
+        ",
+    );
+    run_steps(&mut chippy, 2);
+    assert_eq!(chippy.pc, 0x204);
+}
+
+#[test]
+fn platforms_parse_and_pick_their_quirk_presets() {
+    for (name, platform) in [
+        ("chip8", Platform::Chip8),
+        ("CHIP-8", Platform::Chip8),
+        ("schip", Platform::SuperChip),
+        ("super-chip", Platform::SuperChip),
+        ("xochip", Platform::XoChip),
+        ("XO-CHIP", Platform::XoChip),
+    ] {
+        assert_eq!(name.parse::<Platform>(), Ok(platform));
+    }
+    assert!("chip48".parse::<Platform>().is_err());
+
+    // Columns of the table on `Quirks::for_platform`
+    let quirks = |platform| {
+        let preset = Quirks::for_platform(platform);
+        [
+            preset.memory_increment_i,
+            preset.shift_vy_into_vx,
+            preset.bxnn_uses_vx,
+            preset.clip_sprites,
+            preset.i_overflow_sets_vf,
+            preset.display_wait,
+            preset.logic_resets_vf,
+        ]
+    };
+    assert_eq!(
+        quirks(Platform::Chip8),
+        [true, true, false, true, false, true, true]
+    );
+    assert_eq!(
+        quirks(Platform::SuperChip),
+        [false, false, true, true, false, false, false]
+    );
+    assert_eq!(
+        quirks(Platform::XoChip),
+        [true, true, false, false, false, false, false]
+    );
+
+    let mut chippy = Chippy::new();
+    chippy.set_platform(Platform::SuperChip);
+    assert_eq!(chippy.quirks, Quirks::for_platform(Platform::SuperChip));
+}
+
+#[test]
+fn metrics_count_draws_collisions_and_toggled_pixels() {
+    // The 0 glyph has 14 lit pixels, the 8 glyph 16 of which 14 overlap it
+    let mut chippy = load_program(
+        "
+        LD V1, 8
+        LD F, V0
+        DRW V0, V0, 5
+        LD F, V1
+        DRW V0, V0, 5
+        DRW V1, V0, 5
+        ",
+    );
+    run_steps(&mut chippy, 6);
+    let metrics = chippy.metrics();
+    assert_eq!(metrics.draw_calls, 3);
+    assert_eq!(metrics.collisions, 1);
+    assert_eq!(metrics.pixels_toggled, 14 + 16 + 16);
+
+    chippy.reset_metrics();
+    assert_eq!(chippy.metrics(), EmuMetrics::default());
+}
+
+#[test]
+fn eti_660_roms_load_and_start_at_0x600() {
+    let mut chippy = Chippy::new();
+    chippy.load_address = 0x600;
+    let rom = assemble("LD V0, 0x66").unwrap();
+    chippy.load_bytes(&rom).unwrap();
+    assert_eq!(chippy.pc, 0x600);
+    assert_eq!(chippy.memory[0x600..0x602], rom[..]);
+    assert_eq!(chippy.memory[0x200], 0);
+    run_steps(&mut chippy, 1);
+    assert_eq!((chippy.pc, chippy.v[0]), (0x602, 0x66));
+
+    // Reset goes back to the load address too, and the space left shrinks with it
+    chippy.reset();
+    assert_eq!(chippy.pc, 0x600);
+    assert!(chippy.load_bytes(&[0; 0x1000 - 0x600]).is_ok());
+    assert!(chippy.load_bytes(&[0; 0x1000 - 0x600 + 1]).is_err());
+}
+
+#[test]
+fn dump_shows_registers_timers_and_the_call_stack() {
+    let mut chippy = load_program(
+        "
+        LD VA, 0x3C
+        LD I, 0x123
+        CALL 0x208
+        JP 0x206
+        CALL 0x20C
+        ",
+    );
+    run_steps(&mut chippy, 4);
+    chippy.delay_timer = 5;
+    chippy.sound_timer = 2;
+    assert_eq!(chippy.registers()[0xA], 0x3C);
+    assert_eq!(chippy.call_stack(), [0x206, 0x20A]);
+    assert_eq!(
+        chippy.dump(),
+        "PC: 0x20C  I: 0x123  SP: 2  DT: 5  ST: 2\n\
+         V0: 00  V1: 00  V2: 00  V3: 00  V4: 00  V5: 00  V6: 00  V7: 00\n\
+         V8: 00  V9: 00  VA: 3C  VB: 00  VC: 00  VD: 00  VE: 00  VF: 00\n\
+         Stack: [0x206, 0x20A]\n"
+    );
+}
+
+#[test]
+fn advance_frame_runs_a_frame_of_instructions_and_one_tick() {
+    let mut chippy = load_program(
+        "
+        ADD V0, 1
+        JP 0x200
+        ",
+    );
+    chippy.set_cycles_per_frame(6);
+    chippy.delay_timer = 10;
+    let display = chippy.advance_frame().unwrap();
+    assert_eq!(display.len(), 64 * 32);
+    assert_eq!(chippy.v[0], 3);
+    assert_eq!(chippy.delay_timer, 9);
+    chippy.advance_frame().unwrap();
+    assert_eq!((chippy.v[0], chippy.delay_timer), (6, 8));
+}
+
+#[test]
+fn clock_rate_carries_fractional_cycles_between_frames() {
+    let mut chippy = Chippy::new();
+    let quarter = Duration::from_millis(250);
+    assert_eq!(chippy.cycles_for_elapsed(quarter), 10);
+
+    // 1.5 instructions per frame alternate between 1 and 2
+    chippy.set_clock_hz(6);
+    let cycles: Vec<u32> = (0..4).map(|_| chippy.cycles_for_elapsed(quarter)).collect();
+    assert_eq!(cycles, [1, 2, 1, 2]);
+
+    // A long stall only catches up a quarter of a second
+    chippy.set_clock_hz(100);
+    assert_eq!(chippy.cycles_for_elapsed(Duration::from_secs(2)), 25);
+
+    chippy.set_cycles_per_frame(7);
+    assert_eq!(chippy.clock_hz(), None);
+    assert_eq!(chippy.cycles_for_elapsed(quarter), 7);
+}
+
+#[test]
+fn several_keys_can_be_held_at_once() {
+    let held = [0x1, 0x5, 0xA, 0xF];
+    let mut chippy = Chippy::new();
+    for key in held {
+        chippy.set_key(key, true);
+    }
+    // SKP Vx skips for every held key and only those, SKNP Vx the other way around
+    for (source, skips_when_held) in [("SKP V0", true), ("SKNP V0", false)] {
+        for key in 0..16 {
+            chippy.load_bytes(&assemble(source).unwrap()).unwrap();
+            chippy.v[0] = key as u8;
+            run_steps(&mut chippy, 1);
+            let skipped = chippy.pc == 0x204;
+            assert_eq!(
+                skipped,
+                held.contains(&key) == skips_when_held,
+                "{} with key {:X}",
+                source,
+                key
+            );
+        }
+    }
+
+    chippy.release_all_keys();
+    assert_eq!(chippy.keypad_state(), [false; 16]);
+    chippy.load_bytes(&assemble("SKP V0").unwrap()).unwrap();
+    chippy.v[0] = 0x5;
+    run_steps(&mut chippy, 1);
+    assert_eq!(chippy.pc, 0x202);
+}
+
+#[test]
+fn profiles_parse_settings_and_apply_them_over_the_preset() {
+    let profile = Profile::parse(
+        "
+        # Comments and blank lines are skipped
+
+        platform = schip
+        cycles_per_frame = 30
+        clip_sprites = false
+        color1 = #33FF66
+        color3 = 000080
+        ",
+    )
+    .unwrap();
+    assert_eq!(profile.platform, Some(Platform::SuperChip));
+    assert_eq!(profile.quirks, [("clip_sprites".to_string(), false)]);
+    assert_eq!(profile.cycles_per_frame, Some(30));
+    assert_eq!(
+        profile.colors,
+        [
+            None,
+            Some([0x33, 0xFF, 0x66]),
+            None,
+            Some([0x00, 0x00, 0x80])
+        ]
+    );
+
+    let mut chippy = Chippy::new();
+    profile.apply(&mut chippy);
+    let mut expected = Quirks::for_platform(Platform::SuperChip);
+    expected.clip_sprites = false;
+    assert_eq!(chippy.quirks, expected);
+    assert_eq!(chippy.cycles_per_frame(), 30);
+
+    // Errors name the line
+    for (contents, message) in [
+        ("platform = chip48", "line 1"),
+        ("\nclip_sprites", "line 2: expected key = value"),
+        ("clip_sprite = true", "unknown quirk clip_sprite"),
+        ("clip_sprites = maybe", "expected true or false"),
+        ("color0 = 12345", "invalid colour"),
+        ("cycles_per_frame = fast", "line 1"),
+    ] {
+        let error = Profile::parse(contents).unwrap_err();
+        assert!(error.contains(message), "{}: {}", contents, error);
+    }
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn load_game_applies_and_returns_the_sidecar_profile() {
+    let path = env::temp_dir().join("chippy-profile-test.ch8");
+    let rom = assemble("LD V0, 1").unwrap();
+    fs::write(&path, rom).unwrap();
+    let path = path.to_str().unwrap();
+    let config = format!("{}.cfg", path);
+    fs::write(&config, "cycles_per_frame = 25\ncolor2 = FF0000\n").unwrap();
+
+    let mut chippy = Chippy::new();
+    let profile = chippy.load_game(path);
+    fs::remove_file(path).unwrap();
+    fs::remove_file(&config).unwrap();
+    let profile = profile.unwrap().unwrap();
+    assert_eq!(chippy.cycles_per_frame(), 25);
+    assert_eq!(profile.colors[2], Some([0xFF, 0, 0]));
+}
+
+#[cfg(feature = "sdl")]
+#[test]
+fn roms_from_stdin_have_no_sidecars_and_neutral_capture_names() {
+    use chippy::chippy::frontend::Frontend;
+
+    assert_eq!(Profile::for_rom("-"), Ok(None));
+
+    let path = Frontend::capture_path("-", "png");
+    assert!(
+        path.starts_with("stdin-") && path.ends_with(".png"),
+        "{}",
+        path
+    );
+    let path = Frontend::capture_path("roms/pong.ch8", "gif");
+    assert!(
+        path.starts_with("roms/pong.ch8-") && path.ends_with(".gif"),
+        "{}",
+        path
+    );
+}
+
+#[test]
+fn disassembled_long_loads_include_their_address() {
+    let rom = [0xF0, 0x00, 0x0A, 0x00, 0x60, 0x01];
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&rom).unwrap();
+    let listing = chippy.disassemble(0x200, 2);
+    assert_eq!(
+        listing,
+        [
+            (0x200, "LD I, LONG 0x0A00".to_string()),
+            (0x204, "LD V0, 0x01".to_string()),
+        ]
+    );
+
+    // The listing assembles back into the same ROM
+    let source: Vec<&str> = listing.iter().map(|(_, text)| text.as_str()).collect();
+    assert_eq!(assemble(&source.join("\n")).unwrap(), rom);
+}
+
+#[test]
+fn audio_opcode_decodes_like_it_runs_for_any_x() {
+    // F302 loads the pattern just like F002
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&[0xA2, 0x04, 0xF3, 0x02]).unwrap();
+    run_steps(&mut chippy, 2);
+    assert!(chippy.audio_pattern().is_some());
+    assert_eq!(mnemonic(0xF302), "AUDIO");
+    assert_eq!(mnemonic(0xF002), "AUDIO");
+}
+
+#[test]
+fn loading_a_shorter_rom_clears_the_previous_one() {
+    let mut chippy = Chippy::new();
+    chippy.load_bytes(&[0xAA; 8]).unwrap();
+    chippy.reset();
+    chippy.load_bytes(&[0x60, 0x01]).unwrap();
+    assert_eq!(chippy.memory[0x200..0x202], [0x60, 0x01]);
+    assert!(chippy.memory[0x202..].iter().all(|&byte| byte == 0));
+    // The fonts below the load address stay
+    assert_eq!(chippy.memory[0x050..0x055], FONT[..5]);
+}
+
+#[test]
+fn terminal_rendering_draws_each_lit_pixel() {
+    let mut chippy = Chippy::new();
+    let width = chippy.width();
+    // A short diagonal in the top left corner and a column two pixels tall at x = 3
+    for (x, y) in [(0, 0), (1, 1), (3, 0), (3, 1)] {
+        chippy.display[y * width + x] = 1;
+    }
+
+    let output = chippy.render_to_terminal().into_bytes();
+    let lines: Vec<&str> = std::str::from_utf8(&output).unwrap().lines().collect();
+    assert_eq!(lines.len(), chippy.height());
+    assert_eq!(lines[0], format!("█  █{}", " ".repeat(width - 4)));
+    assert_eq!(lines[1], format!(" █ █{}", " ".repeat(width - 4)));
+    assert!(lines[2..].iter().all(|line| *line == " ".repeat(width)));
+
+    // Half blocks fold both rows into one line
+    let output = chippy.render_to_terminal_half_blocks().into_bytes();
+    let lines: Vec<&str> = std::str::from_utf8(&output).unwrap().lines().collect();
+    assert_eq!(lines.len(), chippy.height() / 2);
+    assert_eq!(lines[0], format!("▀▄ █{}", " ".repeat(width - 4)));
+    assert!(lines[1..].iter().all(|line| *line == " ".repeat(width)));
+}
+
+#[test]
+fn wav_writer_fills_in_the_header_sizes_on_finish() {
+    let mut out = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut out, 8000).unwrap();
+    writer.write_samples(&[0.0, 1.0, -1.0]).unwrap();
+    writer.write_samples(&[0.5, 2.0]).unwrap();
+    assert_eq!(writer.samples(), 5);
+    writer.finish().unwrap();
+
+    let wav = out.into_inner();
+    let u32_at = |offset: usize| u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap());
+    assert_eq!(wav.len(), 44 + 5 * 2);
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(u32_at(4), wav.len() as u32 - 8);
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(u32_at(24), 8000);
+    assert_eq!(u32_at(28), 16000);
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(u32_at(40), 10);
+
+    // 16-bit samples, anything past full scale is clamped
+    let samples: Vec<i16> = wav[44..]
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect();
+    assert_eq!(samples, [0, 32767, -32767, 16383, 32767]);
+}
+
+#[test]
+fn png_encoder_writes_valid_chunks() {
+    // The standard CRC-32 check value
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+    let rgb: Vec<u8> = (0..3 * 2 * 3).collect();
+    let png = encode_png(3, 2, &rgb);
+    assert_eq!(
+        png[..8],
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']
+    );
+
+    // Split the rest into (kind, data) chunks, checking each CRC on the way
+    let mut chunks = Vec::new();
+    let mut rest = &png[8..];
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (body, crc) = rest[4..].split_at(4 + length);
+        assert_eq!(
+            crc32(body),
+            u32::from_be_bytes(crc[..4].try_into().unwrap())
+        );
+        chunks.push((&body[..4], &body[4..]));
+        rest = &crc[4..];
+    }
+    let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| *kind).collect();
+    assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+
+    // 3x2, 8 bits per channel RGB
+    assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+    // IEND's CRC is the same in every PNG
+    assert_eq!(png[png.len() - 4..], [0xAE, 0x42, 0x60, 0x82]);
+
+    // The zlib stream holds each row uncompressed behind a filter byte of 0
+    let idat = chunks[1].1;
+    assert_eq!(idat[..3], [0x78, 0x01, 0x01]);
+    let mut raw = vec![0];
+    raw.extend_from_slice(&rgb[..9]);
+    raw.push(0);
+    raw.extend_from_slice(&rgb[9..]);
+    assert_eq!(idat[7..idat.len() - 4], raw);
+}
+
+// GIF LZW decoder, the inverse of `gif::lzw_encode`
+fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut table: Vec<Vec<u8>> = Vec::new();
+    let mut size = min_code_size + 1;
+    let mut previous: Option<Vec<u8>> = None;
+    let mut out = Vec::new();
+    let (mut buffer, mut bits, mut bytes) = (0u32, 0u8, data.iter());
+    loop {
+        while bits < size {
+            buffer |= (*bytes.next().expect("missing end code") as u32) << bits;
+            bits += 8;
+        }
+        let code = (buffer & ((1 << size) - 1)) as u16;
+        buffer >>= size;
+        bits -= size;
+        if code == clear {
+            table = (0..=end).map(|index| vec![index as u8]).collect();
+            size = min_code_size + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            return out;
+        }
+        let entry = match (table.get(code as usize), &previous) {
+            (Some(entry), _) => entry.clone(),
+            // The code the encoder is defining right now
+            (None, Some(previous)) => [&previous[..], &previous[..1]].concat(),
+            (None, None) => panic!("undefined code {}", code),
+        };
+        out.extend_from_slice(&entry);
+        if let Some(mut previous) = previous.filter(|_| table.len() < 4096) {
+            previous.push(entry[0]);
+            table.push(previous);
+        }
+        if table.len() == 1 << size && size < 12 {
+            size += 1;
+        }
+        previous = Some(entry);
+    }
+}
+
+#[test]
+fn gif_writer_encodes_each_frame() {
+    // A plain frame and a noisy one with enough codes to fill the LZW table and start over
+    let mut display = vec![0; 64 * 32];
+    display[0] = 1;
+    display[64 * 32 - 1] = 3;
+    let first = gif::scale_indices(&gif::frame_indices(&display), 64, 4);
+    assert_eq!(first.len(), 256 * 128);
+    assert_eq!(first[..5], [1, 1, 1, 1, 0]);
+    assert_eq!(first[3 * 256..3 * 256 + 5], [1, 1, 1, 1, 0]);
+    assert_eq!(first[4 * 256], 0);
+    assert_eq!(first[256 * 128 - 1], 3);
+    let mut seed = 1u32;
+    let second: Vec<u8> = (0..256 * 128)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8 & 0b11
+        })
+        .collect();
+
+    let palette = [[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 0, 255]];
+    let mut out = Vec::new();
+    let mut writer = GifWriter::new(&mut out, 256, 128, &palette).unwrap();
+    writer.write_frame(&first, 5).unwrap();
+    writer.write_frame(&second, 10).unwrap();
+    writer.finish().unwrap();
+
+    // Header, logical screen size and the four colour global table
+    assert_eq!(&out[..6], b"GIF89a");
+    assert_eq!(out[6..10], [0, 1, 128, 0]);
+    assert_eq!(out[10], 0x81);
+    assert_eq!(out[13..25], palette.concat());
+    assert_eq!(&out[28..39], b"NETSCAPE2.0");
+    assert_eq!(out.last(), Some(&0x3B));
+
+    // Both frames cover the whole canvas and decode back to their indices
+    let mut rest = &out[44..out.len() - 1];
+    for (frame, delay) in [(&first, 5u16), (&second, 10)] {
+        assert_eq!(rest[..4], [0x21, 0xF9, 0x04, 0x00]);
+        assert_eq!(rest[4..6], delay.to_le_bytes());
+        assert_eq!(rest[8..18], [0x2C, 0, 0, 0, 0, 0, 1, 128, 0, 0]);
+        let min_code_size = rest[18];
+        rest = &rest[19..];
+        let mut data = Vec::new();
+        while rest[0] != 0 {
+            let length = rest[0] as usize;
+            data.extend_from_slice(&rest[1..=length]);
+            rest = &rest[1 + length..];
+        }
+        rest = &rest[1..];
+        assert_eq!(&lzw_decode(&data, min_code_size), frame);
+    }
+    assert!(rest.is_empty());
+}